@@ -288,24 +288,54 @@ function deployBinary(ip: string, port: string): void {
 	const localBinary =
 		`$CARGO_TARGET_DIR/${MUSL_TARGET}/release/examples/${BINARY_NAME}`;
 
-	// Write systemd unit file locally, then SCP it over
+	const isServer = BINARY_NAME === "server";
+
+	// The server sends READY=1 once listening and pings the watchdog,
+	// so systemctl restart only returns once it is serving.
+	const serviceType = isServer
+		? `Type=notify\nWatchdogSec=30`
+		: `Type=simple`;
+
+	// The socket unit holds the port open across restarts of the server,
+	// so requests queue instead of being refused during a deploy.
+	const socketDeps = isServer
+		? `Requires=${SERVICE_NAME}.socket\nAfter=${SERVICE_NAME}.socket\n`
+		: ``;
+	const socketUnit = `[Unit]
+Description=${APP} ${BINARY_NAME} socket
+
+[Socket]
+ListenStream=0.0.0.0:${port}
+# the server is a single process that accepts connections itself
+Accept=no
+
+[Install]
+WantedBy=sockets.target
+`;
+
+	// Write systemd unit files locally, then SCP them over
 	const serviceUnit = `[Unit]
 Description=${APP} ${BINARY_NAME}
 After=network.target
-
+${socketDeps}
 [Service]
-Type=simple
+${serviceType}
 ExecStart=${REMOTE_DIR}/${REMOTE_BINARY_NAME}
+# SIGHUP reloads the TLS certificate
+ExecReload=/bin/kill -HUP $MAINPID
 WorkingDirectory=${REMOTE_DIR}
 Restart=always
 RestartSec=3
-Environment=RUST_LOG=info
+# exit code 78 means the .env config is invalid, restarting won't help
+RestartPreventExitStatus=78
 
 [Install]
 WantedBy=multi-user.target
 `;
 	const localServiceFile = join(INFRA_DIR, `${SERVICE_NAME}.service`);
 	writeFileSync(localServiceFile, serviceUnit);
+	const localSocketFile = join(INFRA_DIR, `${SERVICE_NAME}.socket`);
+	if (isServer) writeFileSync(localSocketFile, socketUnit);
 
 	console.log(`📤 Uploading binary and service file to ${ip}...`);
 	run(
@@ -314,6 +344,11 @@ WantedBy=multi-user.target
 	run(
 		`scp ${SSH_OPTS} ${localServiceFile} ${SSH_USER}@${ip}:/tmp/${SERVICE_NAME}.service`,
 	);
+	if (isServer) {
+		run(
+			`scp ${SSH_OPTS} ${localSocketFile} ${SSH_USER}@${ip}:/tmp/${SERVICE_NAME}.socket`,
+		);
+	}
 
 	const hasEnv = existsSync(".env");
 	if (hasEnv) {
@@ -323,10 +358,21 @@ WantedBy=multi-user.target
 		console.log(`⚠️  No local .env file found — skipping upload.`);
 	}
 
-	// Clean up local temp file
-	try {
-		unlinkSync(localServiceFile);
-	} catch {}
+	// served as static files, unless built into the binary with embed-public
+	const hasPublic = isServer && existsSync("public");
+	if (hasPublic) {
+		console.log(`📤 Uploading public/ to ${ip}...`);
+		run(
+			`tar -czf - public | ssh ${SSH_OPTS} ${SSH_USER}@${ip} 'rm -rf /tmp/public && tar -xzf - -C /tmp'`,
+		);
+	}
+
+	// Clean up local temp files
+	for (const file of [localServiceFile, localSocketFile]) {
+		try {
+			unlinkSync(file);
+		} catch {}
+	}
 
 	console.log("🔄 Installing and restarting service...");
 	const remoteCmd = [
@@ -334,18 +380,34 @@ WantedBy=multi-user.target
 		`sudo mv /tmp/${REMOTE_BINARY_NAME} ${REMOTE_DIR}/${REMOTE_BINARY_NAME}`,
 		`sudo chmod +x ${REMOTE_DIR}/${REMOTE_BINARY_NAME}`,
 		hasEnv ? `sudo mv /tmp/.env ${REMOTE_DIR}/.env` : `true`,
+		hasPublic
+			? `sudo rm -rf ${REMOTE_DIR}/public && sudo mv /tmp/public ${REMOTE_DIR}/public`
+			: `true`,
 		`sudo mv /tmp/${SERVICE_NAME}.service /etc/systemd/system/${SERVICE_NAME}.service`,
+		isServer
+			? `sudo mv /tmp/${SERVICE_NAME}.socket /etc/systemd/system/${SERVICE_NAME}.socket`
+			: `true`,
 		`sudo systemctl daemon-reload`,
+		// the first time, stop the server so the socket unit can take its port.
+		// After that the socket stays up and restarts don't drop connections
+		isServer
+			? `(sudo systemctl is-active --quiet ${SERVICE_NAME}.socket || sudo systemctl stop ${SERVICE_NAME}.service)`
+			: `true`,
+		isServer ? `sudo systemctl enable --now ${SERVICE_NAME}.socket` : `true`,
 		`sudo systemctl enable ${SERVICE_NAME}.service`,
 		`sudo systemctl restart ${SERVICE_NAME}.service`,
-		`sleep 2`,
+		// Type=notify units only finish restarting once ready
+		isServer ? `true` : `sleep 2`,
 		`sudo systemctl is-active ${SERVICE_NAME}.service`,
+		isServer
+			? `curl -fsS --retry 5 --retry-connrefused http://localhost:${port}/readyz`
+			: `true`,
 	].join(" && ");
 	run(`ssh ${SSH_OPTS} ${SSH_USER}@${ip} '${remoteCmd}'`);
 
 	console.log(`\n✅ Binary deployed and service running!`);
 	console.log(`📦 Service: ${SERVICE_NAME}`);
-	if (BINARY_NAME === "server") {
+	if (isServer) {
 		console.log(`🌐 http://${ip}:${port}`);
 	}
 }
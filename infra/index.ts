@@ -45,12 +45,17 @@ Description=Hello Lightsail HTTP Server
 After=network.target
 
 [Service]
-Type=simple
+# the server sends READY=1 once listening and pings the watchdog
+Type=notify
+WatchdogSec=30
 ExecStart=/opt/${appName}/app
+# SIGHUP reloads the TLS certificate
+ExecReload=/bin/kill -HUP $MAINPID
 WorkingDirectory=/opt/${appName}
 Restart=always
 RestartSec=3
-Environment=RUST_LOG=info
+# exit code 78 means the .env config is invalid, restarting won't help
+RestartPreventExitStatus=78
 
 [Install]
 WantedBy=multi-user.target
@@ -86,7 +91,7 @@ const _ipAttachment = new aws.lightsail.StaticIpAttachment(
 );
 
 // ---------------------------------------------------------------------------
-// Firewall – open the server port and SSH
+// Firewall – open the server port, HTTP and HTTPS for TLS and ACME, and SSH
 // ---------------------------------------------------------------------------
 const _publicPorts = new aws.lightsail.InstancePublicPorts(
 	`${prefix}--ports`,
@@ -98,6 +103,17 @@ const _publicPorts = new aws.lightsail.InstancePublicPorts(
 				fromPort: serverPort,
 				toPort: serverPort,
 			},
+			// the HTTPS redirect and ACME HTTP-01 challenges
+			{
+				protocol: "tcp",
+				fromPort: 80,
+				toPort: 80,
+			},
+			{
+				protocol: "tcp",
+				fromPort: 443,
+				toPort: 443,
+			},
 			{
 				protocol: "tcp",
 				fromPort: 22,
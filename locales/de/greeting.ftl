@@ -0,0 +1,8 @@
+world = Welt
+hello = hallo { $name }
+welcome-back = willkommen zurück, { $name }, das macht { $visits ->
+        [one] einen Besuch
+       *[other] { $visits } Besuche
+    }
+visitor-number = du bist der { $number }. Besucher
+name-hint = gib den Parameter { $param } an, um persönlich begrüßt zu werden.
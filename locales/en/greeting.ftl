@@ -0,0 +1,19 @@
+# The greeting, in English. Messages missing from another locale fall back
+# to these.
+
+# Who is greeted when no name is given.
+world = world
+hello = hello { $name }
+# For a returning visitor, with their visits including this one.
+welcome-back = welcome back, { $name }, that makes { $visits ->
+        [one] one visit
+       *[other] { $visits } visits
+    }
+visitor-number = you are the { NUMBER($number, type: "ordinal") ->
+        [one] { $number }st
+        [two] { $number }nd
+        [few] { $number }rd
+       *[other] { $number }th
+    } visitor
+# $param is the parameter, ie `name`, quoted or marked up by the page.
+name-hint = pass the { $param } parameter to receive a warm personal greeting.
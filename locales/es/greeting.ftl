@@ -0,0 +1,8 @@
+world = mundo
+hello = hola { $name }
+welcome-back = bienvenido de nuevo, { $name }, ya van { $visits ->
+        [one] una visita
+       *[other] { $visits } visitas
+    }
+visitor-number = eres el { $number }.º visitante
+name-hint = pasa el parámetro { $param } para recibir un saludo personal.
@@ -0,0 +1,11 @@
+world = le monde
+hello = bonjour { $name }
+welcome-back = bon retour, { $name }, cela fait { $visits ->
+        [one] une visite
+       *[other] { $visits } visites
+    }
+visitor-number = vous êtes le { NUMBER($number, type: "ordinal") ->
+        [one] { $number }er
+       *[other] { $number }e
+    } visiteur
+name-hint = passez le paramètre { $param } pour recevoir un accueil personnalisé.
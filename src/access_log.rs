@@ -0,0 +1,194 @@
+//! One line per request written to stdout, which journald collects.
+//!
+//! The default [`AccessLogFormat::Json`] writes a JSON object per line so
+//! the log can be queried, ie with `journalctl -u hello-lightsail -o cat | jq`.
+//! [`AccessLogFormat::Text`] is easier to read while developing locally.
+use crate::prelude::*;
+use beet::exports::http;
+use beet::prelude::*;
+use serde::Serialize;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// How the [`AccessLog`] writes each request.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// One JSON object per line.
+    #[default]
+    Json,
+    /// A human readable line.
+    Text,
+    /// Write nothing.
+    Off,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "text" => Ok(Self::Text),
+            "off" => Ok(Self::Off),
+            _ => Err("expected json, text or off".into()),
+        }
+    }
+}
+
+/// Writes an [`AccessRecord`] for every request handled by the
+/// [`HelloLightsailPlugin`].
+#[derive(Debug, Default, Clone, Resource)]
+pub struct AccessLog {
+    format: AccessLogFormat,
+}
+
+impl AccessLog {
+    /// Set the format, defaults to [`AccessLogFormat::Json`].
+    pub fn with_format(mut self, format: AccessLogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// The format records are written in.
+    pub fn format(&self) -> AccessLogFormat {
+        self.format
+    }
+
+    /// Write the record to stdout in the configured format.
+    pub fn write(&self, record: &AccessRecord) {
+        let line = match self.format {
+            AccessLogFormat::Json => match serde_json::to_string(record) {
+                Ok(line) => line,
+                Err(err) => {
+                    warn!("Failed to serialize access record: {}", err);
+                    return;
+                }
+            },
+            AccessLogFormat::Text => record.to_string(),
+            AccessLogFormat::Off => return,
+        };
+        // a closed stdout shouldn't take the server down
+        writeln!(std::io::stdout().lock(), "{line}").ok();
+    }
+}
+
+/// A single handled request.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessRecord {
+    /// When the request was received, in RFC 3339 UTC.
+    pub timestamp: String,
+    /// The uppercase method, ie `GET`.
+    pub method: String,
+    /// The request path, ie `/healthz`.
+    pub path: String,
+    /// The query string without the leading `?`, if any.
+    pub query: Option<String>,
+    /// The response status code.
+    pub status: u16,
+    /// Time spent handling the request.
+    pub latency_ms: f64,
+    /// Length of the response body, `None` for streamed bodies.
+    pub bytes: Option<usize>,
+    /// The connected client, see [`PEER_ADDR_HEADER`].
+    pub client_ip: Option<String>,
+    /// The `User-Agent` header.
+    pub user_agent: Option<String>,
+    /// The `X-Request-Id`, see [`ensure_request_id`].
+    pub request_id: Option<String>,
+}
+
+impl AccessRecord {
+    /// Start a record for the request, before it is handled.
+    pub fn new(request: &Request) -> Self {
+        let query = request.query_string();
+        Self {
+            timestamp: rfc3339(SystemTime::now()),
+            method: request.method().to_string().to_uppercase(),
+            path: request.path_string(),
+            query: (!query.is_empty()).then_some(query),
+            status: 0,
+            latency_ms: 0.,
+            bytes: None,
+            client_ip: peer_addr(request).map(|addr| addr.ip().to_string()),
+            user_agent: request.get_header("user-agent").map(Into::into),
+            request_id: request.get_header("x-request-id").map(Into::into),
+        }
+    }
+
+    /// Complete the record with the response.
+    pub fn finish(mut self, response: &Response, elapsed: Duration) -> Self {
+        self.status = http::StatusCode::from(response.status()).as_u16();
+        self.latency_ms = elapsed.as_secs_f64() * 1000.;
+        self.bytes = match &response.body {
+            // a static file the listener streams, see `STATIC_FILE_MARKER`
+            Body::Bytes(bytes) if bytes.is_empty() => response
+                .response_parts()
+                .get_header("content-length")
+                .and_then(|length| length.parse().ok())
+                .or(Some(0)),
+            Body::Bytes(bytes) => Some(bytes.len()),
+            Body::Stream(_) => None,
+        };
+        self
+    }
+}
+
+impl std::fmt::Display for AccessRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let query = self
+            .query
+            .as_ref()
+            .map(|query| format!("?{query}"))
+            .unwrap_or_default();
+        let bytes = self
+            .bytes
+            .map(|bytes| bytes.to_string())
+            .unwrap_or_else(|| "-".into());
+        write!(
+            f,
+            "{} {} {} {}{} {} {}B {:.2}ms {:?} {}",
+            self.timestamp,
+            self.client_ip.as_deref().unwrap_or("-"),
+            self.method,
+            self.path,
+            query,
+            self.status,
+            bytes,
+            self.latency_ms,
+            self.user_agent.as_deref().unwrap_or("-"),
+            self.request_id.as_deref().unwrap_or("-"),
+        )
+    }
+}
+
+/// Format as `2024-01-31T12:00:00.000Z`.
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // civil date from days since the epoch, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3_600,
+        secs_of_day % 3_600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
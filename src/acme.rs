@@ -0,0 +1,645 @@
+//! Certificates from an ACME certificate authority such as Let's Encrypt.
+//!
+//! The [`AcmeConfig`] domains are ordered from the CA in a background
+//! thread, validated with HTTP-01 challenges and written to the ACME
+//! directory, `acme/` in the working directory, from which the HTTPS
+//! listener picks them up without a restart. The chain and its key are stored together in
+//! `certificate.pem` and replaced with one rename, so they always match.
+//! Until the first certificate is issued, or if the stored one can't be
+//! loaded, an expired self-signed placeholder is served.
+//!
+//! The CA validates each domain by fetching
+//! `/.well-known/acme-challenge/{token}` over plain HTTP on port 80, which
+//! the [`HttpsRedirect`] listener answers from [`AcmeChallenges`] instead of
+//! redirecting.
+//!
+//! The certificate is checked twice a day and renewed once it is within
+//! [`AcmeConfig::renew_before`] of expiring.
+use crate::prelude::*;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use beet::prelude::*;
+use rcgen::CertificateParams;
+use rcgen::KeyPair;
+use ring::rand::SystemRandom;
+use ring::signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+use ring::signature::EcdsaKeyPair;
+use ring::signature::KeyPair as _;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// The Let's Encrypt production directory.
+pub const LETS_ENCRYPT_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+/// The Let's Encrypt staging directory, for testing without rate limits.
+pub const LETS_ENCRYPT_STAGING_DIRECTORY: &str =
+    "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+/// The route the CA fetches HTTP-01 challenge responses from.
+pub const ACME_CHALLENGE_ROUTE: &str = "/.well-known/acme-challenge/{token}";
+
+/// How often the certificate expiry is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// How long to wait after the first failed order, doubling up to [`MAX_RETRY`].
+const MIN_RETRY: Duration = Duration::from_secs(60);
+
+/// The longest wait between failed orders.
+const MAX_RETRY: Duration = Duration::from_secs(60 * 60);
+
+/// How often pending authorizations and orders are polled.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How many times pending authorizations and orders are polled.
+const POLL_ATTEMPTS: usize = 30;
+
+/// Which domains to obtain a certificate for, and from where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcmeConfig {
+    domains: Vec<String>,
+    directory_url: String,
+    contact: Option<String>,
+    dir: PathBuf,
+    ca_root: Option<PathBuf>,
+    renew_before: Duration,
+}
+
+impl AcmeConfig {
+    /// Obtain a certificate for the domains from Let's Encrypt.
+    pub fn new(domains: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            domains: domains.into_iter().map(Into::into).collect(),
+            directory_url: LETS_ENCRYPT_DIRECTORY.into(),
+            contact: None,
+            dir: PathBuf::from("acme"),
+            ca_root: None,
+            renew_before: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+
+    /// Set the ACME directory of the CA, ie a local Pebble instance at
+    /// `https://localhost:14000/dir`.
+    pub fn with_directory_url(mut self, url: impl Into<String>) -> Self {
+        self.directory_url = url.into();
+        self
+    }
+
+    /// Set the email address the CA sends expiry warnings to.
+    pub fn with_contact(mut self, email: impl Into<String>) -> Self {
+        self.contact = Some(email.into());
+        self
+    }
+
+    /// Set the directory the account key, certificate and private key are
+    /// stored in, defaults to `acme`.
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    /// Trust only this PEM root certificate when connecting to the CA,
+    /// ie the Pebble test root.
+    pub fn with_ca_root(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ca_root = Some(path.into());
+        self
+    }
+
+    /// Set how long before expiry the certificate is renewed, defaults to
+    /// 30 days.
+    pub fn with_renew_before(mut self, renew_before: Duration) -> Self {
+        self.renew_before = renew_before;
+        self
+    }
+
+    /// The domains on the certificate.
+    pub fn domains(&self) -> &[String] {
+        &self.domains
+    }
+
+    /// The ACME directory of the CA.
+    pub fn directory_url(&self) -> &str {
+        &self.directory_url
+    }
+
+    /// The email address registered with the CA.
+    pub fn contact(&self) -> Option<&str> {
+        self.contact.as_deref()
+    }
+
+    /// The directory the keys and certificate are stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The root certificate trusted when connecting to the CA, if not the
+    /// public web roots.
+    pub fn ca_root(&self) -> Option<&Path> {
+        self.ca_root.as_deref()
+    }
+
+    /// How long before expiry the certificate is renewed.
+    pub fn renew_before(&self) -> Duration {
+        self.renew_before
+    }
+
+    /// The file the HTTPS listener serves, holding both the certificate
+    /// chain and its key.
+    pub fn tls(&self) -> TlsConfig {
+        let path = self.dir.join("certificate.pem");
+        TlsConfig::new(path.clone(), path)
+    }
+
+    /// Write a self-signed placeholder if no certificate has been issued
+    /// yet, or the stored one can't be loaded, so the HTTPS listener can
+    /// start.
+    ///
+    /// The placeholder has already expired, so it is replaced straight away.
+    pub fn ensure_certificate(&self) -> std::io::Result<()> {
+        let tls = self.tls();
+        if tls.cert_path().exists() {
+            match TlsCertificate::load(tls) {
+                Ok(_) => return Ok(()),
+                Err(err) => warn!("Ordering a new certificate: {}", err),
+            }
+        }
+        let key = KeyPair::generate().map_err(std::io::Error::other)?;
+        let mut params =
+            CertificateParams::new(self.domains.clone()).map_err(std::io::Error::other)?;
+        params.not_before = rcgen::date_time_ymd(1975, 1, 1);
+        params.not_after = rcgen::date_time_ymd(1975, 1, 2);
+        let cert = params.self_signed(&key).map_err(std::io::Error::other)?;
+        self.install(&cert.pem(), &key.serialize_pem())
+    }
+
+    /// Replace the stored certificate chain and key with a single rename,
+    /// so a crash never leaves a key that doesn't match its certificate.
+    fn install(&self, chain: &str, key: &str) -> std::io::Result<()> {
+        let contents = format!("{}\n{}", chain.trim_end(), key);
+        write_private(self.tls().cert_path(), &contents)
+    }
+
+    fn account_key_path(&self) -> PathBuf {
+        self.dir.join("account.pem")
+    }
+}
+
+/// Key authorizations for the pending HTTP-01 challenges, by token.
+///
+/// Shared between the renewal thread and the [`acme_challenge`] route.
+#[derive(Debug, Default, Clone, Resource)]
+pub struct AcmeChallenges(Arc<RwLock<HashMap<String, String>>>);
+
+impl AcmeChallenges {
+    /// The key authorization for the token, if its challenge is pending.
+    pub fn get(&self, token: &str) -> Option<String> {
+        self.0.read().unwrap().get(token).cloned()
+    }
+
+    fn insert(&self, token: String, key_authorization: String) {
+        self.0.write().unwrap().insert(token, key_authorization);
+    }
+
+    fn remove(&self, token: &str) {
+        self.0.write().unwrap().remove(token);
+    }
+}
+
+/// `GET /.well-known/acme-challenge/{token}`, the key authorization for a
+/// pending challenge.
+pub fn acme_challenge(server: &mut EntityWorldMut, request: Request) -> Response {
+    let key_authorization = request
+        .get_param("token")
+        .and_then(|token| server.world().get_resource::<AcmeChallenges>()?.get(token));
+    match key_authorization {
+        Some(key_authorization) => Response::ok_body(key_authorization, "application/octet-stream"),
+        None => not_found(&request),
+    }
+}
+
+/// Keep the certificate issued and renewed in a background thread,
+/// reloading the [`TlsCertificate`] whenever a new one is written.
+///
+/// The ACME client blocks on network requests, so it runs on its own
+/// thread rather than a task pool.
+pub fn spawn_acme_renewal(
+    config: AcmeConfig,
+    certificate: TlsCertificate,
+    challenges: AcmeChallenges,
+) -> std::io::Result<()> {
+    std::thread::Builder::new()
+        .name("acme".into())
+        .spawn(move || renew_forever(config, certificate, challenges))?;
+    Ok(())
+}
+
+fn renew_forever(config: AcmeConfig, certificate: TlsCertificate, challenges: AcmeChallenges) {
+    let mut retry = MIN_RETRY;
+    loop {
+        let remaining = match time_until_renewal(&config) {
+            Ok(remaining) => remaining,
+            Err(err) => {
+                warn!("Failed to read certificate expiry, renewing: {}", err);
+                Duration::ZERO
+            }
+        };
+        if !remaining.is_zero() {
+            std::thread::sleep(remaining.min(CHECK_INTERVAL));
+            continue;
+        }
+
+        info!(
+            "Ordering certificate for {} from {}",
+            config.domains.join(", "),
+            config.directory_url
+        );
+        let issued = AcmeClient::new(&config)
+            .and_then(|mut client| client.order(&config, &challenges))
+            .and_then(|(chain, key)| {
+                config.install(&chain, &key)?;
+                certificate.reload()
+            });
+        match issued {
+            Ok(()) => {
+                info!("Installed certificate for {}", config.domains.join(", "));
+                retry = MIN_RETRY;
+            }
+            Err(err) => {
+                error!(
+                    "Failed to obtain certificate, retrying in {}s: {}",
+                    retry.as_secs(),
+                    err
+                );
+                std::thread::sleep(retry);
+                retry = (retry * 2).min(MAX_RETRY);
+            }
+        }
+    }
+}
+
+/// Time until the stored certificate is due for renewal, zero if it is due.
+fn time_until_renewal(config: &AcmeConfig) -> std::io::Result<Duration> {
+    let pem = std::fs::read(config.tls().cert_path())?;
+    let (_, pem) = x509_parser::pem::parse_x509_pem(&pem)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))?;
+    let cert = pem
+        .parse_x509()
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))?;
+    let not_after = u64::try_from(cert.validity().not_after.timestamp()).unwrap_or(0);
+    let renew_at = (UNIX_EPOCH + Duration::from_secs(not_after))
+        .checked_sub(config.renew_before)
+        .unwrap_or(UNIX_EPOCH);
+    Ok(renew_at
+        .duration_since(SystemTime::now())
+        .unwrap_or_default())
+}
+
+/// Write a file only the owner can read, replacing it atomically.
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".tmp");
+    let tmp_path = path.with_file_name(file_name);
+    {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file: File = options.open(&tmp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+    }
+    std::fs::rename(&tmp_path, path)
+}
+
+fn acme_error(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::other(message.into())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: String,
+}
+
+/// A minimal RFC 8555 client signing requests with an ES256 account key.
+struct AcmeClient {
+    agent: ureq::Agent,
+    directory: Directory,
+    rng: SystemRandom,
+    key: EcdsaKeyPair,
+    jwk: Value,
+    thumbprint: String,
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+impl AcmeClient {
+    fn new(config: &AcmeConfig) -> std::io::Result<Self> {
+        let mut tls = ureq::tls::TlsConfig::builder();
+        if let Some(path) = &config.ca_root {
+            let pem = std::fs::read(path)?;
+            let root = ureq::tls::Certificate::from_pem(&pem).map_err(|err| {
+                acme_error(format!("Invalid CA root {}: {}", path.display(), err))
+            })?;
+            tls = tls.root_certs(ureq::tls::RootCerts::new_with_certs(&[root]));
+        }
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .tls_config(tls.build())
+            .http_status_as_error(false)
+            .timeout_global(Some(Duration::from_secs(30)))
+            .build()
+            .into();
+
+        let mut response = agent
+            .get(&config.directory_url)
+            .call()
+            .map_err(std::io::Error::other)?;
+        let directory = read_json(&mut response)?;
+
+        let rng = SystemRandom::new();
+        let key = EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            &account_key(config)?,
+            &rng,
+        )
+        .map_err(|err| acme_error(format!("Invalid account key: {err}")))?;
+        // an uncompressed point, 0x04 followed by x and y
+        let point = key.public_key().as_ref();
+        let (x, y) = (
+            URL_SAFE_NO_PAD.encode(&point[1..33]),
+            URL_SAFE_NO_PAD.encode(&point[33..65]),
+        );
+        // RFC 7638, the required members in lexicographic order
+        let thumbprint = URL_SAFE_NO_PAD.encode(ring::digest::digest(
+            &ring::digest::SHA256,
+            format!(r#"{{"crv":"P-256","kty":"EC","x":"{x}","y":"{y}"}}"#).as_bytes(),
+        ));
+        Ok(Self {
+            agent,
+            directory,
+            rng,
+            key,
+            jwk: json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y }),
+            thumbprint,
+            kid: None,
+            nonce: None,
+        })
+    }
+
+    /// Order a certificate for the configured domains, returning the PEM
+    /// certificate chain and private key.
+    fn order(
+        &mut self,
+        config: &AcmeConfig,
+        challenges: &AcmeChallenges,
+    ) -> std::io::Result<(String, String)> {
+        let mut account = json!({ "termsOfServiceAgreed": true });
+        if let Some(contact) = &config.contact {
+            account["contact"] = json!([format!("mailto:{contact}")]);
+        }
+        let new_account = self.directory.new_account.clone();
+        let response = self.post(&new_account, Some(&account))?;
+        self.kid = Some(location(&response)?);
+
+        let identifiers = config
+            .domains
+            .iter()
+            .map(|domain| json!({ "type": "dns", "value": domain }))
+            .collect::<Vec<_>>();
+        let new_order = self.directory.new_order.clone();
+        let mut response = self.post(&new_order, Some(&json!({ "identifiers": identifiers })))?;
+        let order_url = location(&response)?;
+        let order: Order = read_json(&mut response)?;
+
+        for url in &order.authorizations {
+            self.authorize(url, challenges)?;
+        }
+
+        let key = KeyPair::generate().map_err(std::io::Error::other)?;
+        let csr = CertificateParams::new(config.domains.clone())
+            .and_then(|params| params.serialize_request(&key))
+            .map_err(std::io::Error::other)?;
+        self.post(
+            &order.finalize,
+            Some(&json!({ "csr": URL_SAFE_NO_PAD.encode(csr.der()) })),
+        )?;
+        let order: Order = self.poll(&order_url)?;
+        let certificate_url = order
+            .certificate
+            .ok_or_else(|| acme_error("Valid order has no certificate url"))?;
+        let mut response = self.post(&certificate_url, None)?;
+        let chain = response
+            .body_mut()
+            .read_to_string()
+            .map_err(std::io::Error::other)?;
+        Ok((chain, key.serialize_pem()))
+    }
+
+    /// Respond to the HTTP-01 challenge of a pending authorization and
+    /// wait for the CA to validate it.
+    fn authorize(&mut self, url: &str, challenges: &AcmeChallenges) -> std::io::Result<()> {
+        let authorization: Authorization = read_json(&mut self.post(url, None)?)?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let challenge = authorization
+            .challenges
+            .into_iter()
+            .find(|challenge| challenge.kind == "http-01")
+            .ok_or_else(|| acme_error(format!("No http-01 challenge offered for {url}")))?;
+
+        let key_authorization = format!("{}.{}", challenge.token, self.thumbprint);
+        challenges.insert(challenge.token.clone(), key_authorization);
+        let result = self
+            .post(&challenge.url, Some(&json!({})))
+            .and_then(|_| self.poll::<Value>(url));
+        challenges.remove(&challenge.token);
+        result.map(|_| ())
+    }
+
+    /// Fetch the resource until its status is `valid`.
+    fn poll<T: DeserializeOwned>(&mut self, url: &str) -> std::io::Result<T> {
+        for _ in 0..POLL_ATTEMPTS {
+            let resource: Value = read_json(&mut self.post(url, None)?)?;
+            match resource["status"].as_str() {
+                Some("valid") => return serde_json::from_value(resource).map_err(Into::into),
+                Some("invalid") => {
+                    return Err(acme_error(format!(
+                        "{} is invalid: {}",
+                        url,
+                        problem_detail(&resource)
+                    )));
+                }
+                _ => std::thread::sleep(POLL_INTERVAL),
+            }
+        }
+        Err(acme_error(format!("{url} is still pending")))
+    }
+
+    /// Send a signed request, `None` for a POST-as-GET.
+    fn post(
+        &mut self,
+        url: &str,
+        payload: Option<&Value>,
+    ) -> std::io::Result<ureq::http::Response<ureq::Body>> {
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => self.new_nonce()?,
+            };
+            let body = self.sign(url, &nonce, payload)?;
+            let mut response = self
+                .agent
+                .post(url)
+                .header("content-type", "application/jose+json")
+                .send(&body)
+                .map_err(std::io::Error::other)?;
+            self.nonce = replay_nonce(&response);
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            let problem: Value = read_json(&mut response).unwrap_or_default();
+            // nonces expire, the CA sends a fresh one with the error
+            if !retried && problem["type"] == "urn:ietf:params:acme:error:badNonce" {
+                retried = true;
+                continue;
+            }
+            return Err(acme_error(format!(
+                "{} from {}: {}",
+                response.status(),
+                url,
+                problem_detail(&problem)
+            )));
+        }
+    }
+
+    fn new_nonce(&mut self) -> std::io::Result<String> {
+        let response = self
+            .agent
+            .head(&self.directory.new_nonce)
+            .call()
+            .map_err(std::io::Error::other)?;
+        replay_nonce(&response).ok_or_else(|| acme_error("No Replay-Nonce in newNonce response"))
+    }
+
+    /// A flattened JWS, identified by the account url once registered.
+    fn sign(&self, url: &str, nonce: &str, payload: Option<&Value>) -> std::io::Result<String> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk.clone(),
+        }
+        let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+        let payload = payload
+            .map(|payload| URL_SAFE_NO_PAD.encode(payload.to_string()))
+            .unwrap_or_default();
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+            .map_err(|_| acme_error("Failed to sign request"))?;
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+        })
+        .to_string())
+    }
+}
+
+/// Load the PKCS#8 account key, generating one on first use.
+fn account_key(config: &AcmeConfig) -> std::io::Result<Vec<u8>> {
+    let path = config.account_key_path();
+    let key = match std::fs::read_to_string(&path) {
+        Ok(pem) => KeyPair::from_pem(&pem).map_err(|err| {
+            acme_error(format!("Invalid account key {}: {}", path.display(), err))
+        })?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let key = KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256)
+                .map_err(std::io::Error::other)?;
+            write_private(&path, &key.serialize_pem())?;
+            info!("Generated ACME account key {}", path.display());
+            key
+        }
+        Err(err) => return Err(err),
+    };
+    Ok(key.serialize_der())
+}
+
+fn read_json<T: DeserializeOwned>(
+    response: &mut ureq::http::Response<ureq::Body>,
+) -> std::io::Result<T> {
+    let body = response
+        .body_mut()
+        .read_to_string()
+        .map_err(std::io::Error::other)?;
+    serde_json::from_str(&body).map_err(Into::into)
+}
+
+fn location(response: &ureq::http::Response<ureq::Body>) -> std::io::Result<String> {
+    response
+        .headers()
+        .get("location")
+        .and_then(|value| value.to_str().ok())
+        .map(Into::into)
+        .ok_or_else(|| acme_error("No Location in response"))
+}
+
+fn replay_nonce(response: &ureq::http::Response<ureq::Body>) -> Option<String> {
+    response
+        .headers()
+        .get("replay-nonce")
+        .and_then(|value| value.to_str().ok())
+        .map(Into::into)
+}
+
+/// The `detail` of an RFC 7807 problem, or of the first failed challenge.
+fn problem_detail(resource: &Value) -> String {
+    resource["error"]["detail"]
+        .as_str()
+        .or_else(|| resource["detail"].as_str())
+        .or_else(|| {
+            resource["challenges"]
+                .as_array()?
+                .iter()
+                .find_map(|challenge| challenge["error"]["detail"].as_str())
+        })
+        .unwrap_or("no details")
+        .to_string()
+}
@@ -0,0 +1,106 @@
+//! systemd socket activation.
+//!
+//! With a `.socket` unit systemd binds the port itself and passes the
+//! listening socket to the service as file descriptor 3, announced by
+//! `LISTEN_FDS` and `LISTEN_PID`. The socket stays open while the service
+//! restarts, so connections queue in its backlog instead of being refused.
+//!
+//! Without these variables the [`HttpListener`] binds its own address.
+//! They are left set, as changing the environment of a running process is
+//! unsound, but `LISTEN_PID` stops child processes from taking the sockets.
+use beet::prelude::*;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::os::fd::FromRawFd;
+use std::os::fd::RawFd;
+
+/// The first file descriptor passed by systemd, `SD_LISTEN_FDS_START`.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Listening sockets passed by systemd, taken in order by each
+/// [`HttpListener`] instead of binding its own address.
+#[derive(Debug, Resource)]
+pub struct InheritedSockets(Vec<TcpListener>);
+
+/// Defaults to [`InheritedSockets::from_env`].
+impl Default for InheritedSockets {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+impl InheritedSockets {
+    /// No inherited sockets, every listener binds its own address.
+    pub fn none() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Take ownership of the sockets announced by `LISTEN_FDS`,
+    /// if `LISTEN_PID` is this process.
+    pub fn from_env() -> Self {
+        Self::from_vars(|key| std::env::var(key).ok())
+    }
+
+    /// Take ownership of the sockets announced by `LISTEN_FDS` using the
+    /// given variable lookup, if `LISTEN_PID` is this process.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let for_us =
+            var("LISTEN_PID").is_some_and(|pid| pid.trim() == std::process::id().to_string());
+        let count = var("LISTEN_FDS")
+            .and_then(|count| count.trim().parse::<RawFd>().ok())
+            .unwrap_or(0);
+        if !for_us || count <= 0 {
+            return Self::none();
+        }
+
+        let sockets = (LISTEN_FDS_START..LISTEN_FDS_START + count)
+            .filter_map(|fd| {
+                // SAFETY: systemd passes these descriptors to this process
+                // only, as checked with LISTEN_PID, and nothing else owns them.
+                let listener = unsafe { TcpListener::from_raw_fd(fd) };
+                match listener.local_addr() {
+                    Ok(addr) => {
+                        info!("Inherited socket {} from systemd for {}", fd, addr);
+                        Some(listener)
+                    }
+                    Err(err) => {
+                        warn!("Ignoring inherited fd {}, not a tcp socket: {}", fd, err);
+                        None
+                    }
+                }
+            })
+            .collect();
+        Self(sockets)
+    }
+
+    /// Use the given sockets, ie for tests.
+    pub fn new(sockets: impl IntoIterator<Item = TcpListener>) -> Self {
+        Self(sockets.into_iter().collect())
+    }
+
+    /// The number of sockets not yet taken by a listener.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether every socket has been taken.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether an inherited socket bound to `inherited` serves the
+    /// `configured` address. An unspecified ip or port `0` matches any.
+    pub fn serves(configured: SocketAddr, inherited: SocketAddr) -> bool {
+        (configured.ip().is_unspecified() || configured.ip() == inherited.ip())
+            && (configured.port() == 0 || configured.port() == inherited.port())
+    }
+
+    /// Take the next socket, if any.
+    pub fn take(&mut self) -> Option<TcpListener> {
+        if self.0.is_empty() {
+            None
+        } else {
+            Some(self.0.remove(0))
+        }
+    }
+}
@@ -0,0 +1,400 @@
+//! Authenticated routes for managing the running server.
+//!
+//! The routes are only added by [`ServerConfig::with_admin`], and require an
+//! `Authorization: Bearer <token>` header with the [`AdminConfig`] token.
+//! Every authorized request is appended to the audit log as one JSON line
+//! and synced to disk before its action is applied, and refused with a
+//! `503` if that fails. Rejected requests are recorded at most once every
+//! [`FAILED_AUTH_AUDIT_INTERVAL`], with the number left out since, so
+//! unauthenticated clients can't flood the log.
+//!
+//! | Route                             | Action                              |
+//! |-----------------------------------|-------------------------------------|
+//! | `GET /admin/state`                | Dump the count, names and settings  |
+//! | `PUT /admin/count?value=`         | Set the visitor count               |
+//! | `DELETE /admin/count`             | Reset the visitor count to zero     |
+//! | `PUT /admin/maintenance?enabled=` | Toggle [`Maintenance`] mode         |
+//! | `PUT /admin/log-level?level=`     | Change the [`log_level`]            |
+use crate::access_log::rfc3339;
+use crate::prelude::*;
+use beet::exports::http;
+use beet::prelude::*;
+use serde::Serialize;
+use serde_json::Value;
+use serde_json::json;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Route patterns starting with this are admin routes.
+pub const ADMIN_PREFIX: &str = "/admin/";
+
+/// Shorter tokens are rejected by [`AppConfig::from_vars`].
+pub const MIN_ADMIN_TOKEN_CHARS: usize = 16;
+
+/// How often a request with a missing or wrong token is audited.
+pub const FAILED_AUTH_AUDIT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The admin token and where admin actions are recorded.
+///
+/// Only a digest of the token is kept, so it can't leak through `Debug`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminConfig {
+    token_digest: Vec<u8>,
+    audit_log: PathBuf,
+}
+
+impl AdminConfig {
+    /// Accept requests bearing this token.
+    ///
+    /// # Panics
+    ///
+    /// If the token is empty.
+    pub fn new(token: &str) -> Self {
+        assert!(!token.is_empty(), "the admin token must not be empty");
+        Self {
+            token_digest: digest(token),
+            audit_log: PathBuf::from("audit.log"),
+        }
+    }
+
+    /// Set the file admin actions are appended to, defaults to `audit.log`.
+    pub fn with_audit_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.audit_log = path.into();
+        self
+    }
+
+    /// The file admin actions are appended to.
+    pub fn audit_log(&self) -> &Path {
+        &self.audit_log
+    }
+
+    /// Whether the request carries the admin token.
+    pub fn is_authorized(&self, request: &Request) -> bool {
+        // comparing digests doesn't leak the token through timing
+        bearer_token(request).is_some_and(|token| digest(token) == self.token_digest)
+    }
+}
+
+fn digest(token: &str) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA256, token.as_bytes())
+        .as_ref()
+        .to_vec()
+}
+
+fn bearer_token(request: &Request) -> Option<&str> {
+    let (scheme, token) = request
+        .get_header("authorization")?
+        .trim()
+        .split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| token.trim())
+        .filter(|token| !token.is_empty())
+}
+
+/// While enabled, every route except the probes, `/metrics` and the admin
+/// routes answers [`under_maintenance`]. Resets when the server restarts.
+#[derive(Debug, Default, Clone, Resource)]
+pub struct Maintenance {
+    enabled: bool,
+}
+
+impl Maintenance {
+    /// Whether maintenance mode is on.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Turn maintenance mode on or off.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Whether requests to the route pattern are served.
+    pub fn allows(&self, pattern: &str) -> bool {
+        !self.enabled
+            || matches!(pattern, "/healthz" | "/readyz" | "/metrics")
+            || pattern.starts_with(ADMIN_PREFIX)
+    }
+}
+
+/// Samples the rejected admin requests that are audited, see
+/// [`FAILED_AUTH_AUDIT_INTERVAL`].
+#[derive(Debug, Default, Resource)]
+pub struct FailedAuthAudit {
+    last: Option<Instant>,
+    suppressed: u32,
+}
+
+impl FailedAuthAudit {
+    /// Count a rejected request, returning how many went unaudited before
+    /// it if this one should be audited.
+    fn sample(&mut self, now: Instant) -> Option<u32> {
+        match self.last {
+            Some(last) if now.duration_since(last) < FAILED_AUTH_AUDIT_INTERVAL => {
+                self.suppressed += 1;
+                None
+            }
+            _ => {
+                self.last = Some(now);
+                Some(std::mem::take(&mut self.suppressed))
+            }
+        }
+    }
+}
+
+/// A `503` for routes that are off during [`Maintenance`], see
+/// [`error_response`].
+pub fn under_maintenance(request: &Request) -> Response {
+    error_response(
+        request,
+        http::StatusCode::SERVICE_UNAVAILABLE,
+        "down for maintenance",
+    )
+}
+
+/// `GET /admin/state`, the visitor count, names and runtime settings.
+pub fn admin_state(server: &mut EntityWorldMut, request: Request) -> Response {
+    admin_action(server, &request, "dump_state", |server, _request| {
+        let names: Vec<Value> = server
+            .get::<NameCounts>()
+            .map(NameCounts::to_vec)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, visits)| json!({ "name": name, "visits": visits }))
+            .collect();
+        let state = json!({
+            "count": server.get::<Count>().map(|count| count.0).unwrap_or(0),
+            "names": names,
+            "maintenance": server.resource::<Maintenance>().is_enabled(),
+            "log_level": log_level().to_string().to_lowercase(),
+            "uptime_secs": server.resource::<Metrics>().uptime().as_secs(),
+        });
+        Ok((state, None))
+    })
+}
+
+/// `PUT /admin/count?value=`, set the visitor count.
+pub fn admin_set_count(server: &mut EntityWorldMut, request: Request) -> Response {
+    admin_action(server, &request, "set_count", |server, request| {
+        let value = request
+            .get_param("value")
+            .and_then(|value| value.trim().parse::<u32>().ok())
+            .ok_or("expected ?value= with a non-negative integer")?;
+        plan_count(server, value)
+    })
+}
+
+/// `DELETE /admin/count`, reset the visitor count to zero.
+pub fn admin_reset_count(server: &mut EntityWorldMut, request: Request) -> Response {
+    admin_action(server, &request, "reset_count", |server, _request| {
+        plan_count(server, 0)
+    })
+}
+
+fn plan_count(server: &EntityWorldMut, value: u32) -> Planned {
+    let previous = server
+        .get::<Count>()
+        .ok_or("the server has no visitor count")?
+        .0;
+    let result = json!({ "count": value, "previous": previous });
+    Ok((result, Some(AdminChange::Count(value))))
+}
+
+/// `PUT /admin/maintenance?enabled=`, turn [`Maintenance`] mode on or off.
+pub fn admin_maintenance(server: &mut EntityWorldMut, request: Request) -> Response {
+    admin_action(server, &request, "set_maintenance", |server, request| {
+        let enabled = match request.get_param("enabled").map(|value| value.trim()) {
+            Some("true") => true,
+            Some("false") => false,
+            _ => return Err("expected ?enabled=true or ?enabled=false".into()),
+        };
+        let previous = server.resource::<Maintenance>().is_enabled();
+        let result = json!({ "maintenance": enabled, "previous": previous });
+        Ok((result, Some(AdminChange::Maintenance(enabled))))
+    })
+}
+
+/// `PUT /admin/log-level?level=`, change the [`log_level`].
+pub fn admin_log_level(server: &mut EntityWorldMut, request: Request) -> Response {
+    admin_action(server, &request, "set_log_level", |_server, request| {
+        let level = request
+            .get_param("level")
+            .and_then(|level| level.trim().parse::<Level>().ok())
+            .ok_or("expected ?level= with trace, debug, info, warn or error")?;
+        let result = json!({
+            "log_level": level.to_string().to_lowercase(),
+            "previous": log_level().to_string().to_lowercase(),
+        });
+        Ok((result, Some(AdminChange::LogLevel(level))))
+    })
+}
+
+/// The response body of an admin action and the change it makes, or why
+/// the request is invalid.
+type Planned = Result<(Value, Option<AdminChange>), String>;
+
+/// A change to the running server, applied once it has been audited.
+#[derive(Debug, Clone, Copy)]
+enum AdminChange {
+    Count(u32),
+    Maintenance(bool),
+    LogLevel(Level),
+}
+
+impl AdminChange {
+    fn apply(self, server: &mut EntityWorldMut) {
+        match self {
+            Self::Count(value) => {
+                if let Some(mut count) = server.get_mut::<Count>() {
+                    count.0 = value;
+                }
+                let update = CountUpdate {
+                    count: value,
+                    name: None,
+                };
+                server.resource::<VisitEvents>().publish(&update);
+                server.resource::<LiveCount>().publish(update);
+            }
+            Self::Maintenance(enabled) => {
+                server.resource_mut::<Maintenance>().set_enabled(enabled);
+            }
+            Self::LogLevel(level) => set_log_level(level),
+        }
+    }
+}
+
+/// A line in the audit log.
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    timestamp: String,
+    action: &'a str,
+    status: u16,
+    client_ip: Option<String>,
+    request_id: Option<&'a str>,
+    user_agent: Option<&'a str>,
+    query: Option<String>,
+    /// The response body, omitted for reads.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<&'a Value>,
+    /// Rejected requests left out since the last one recorded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suppressed: Option<u32>,
+}
+
+impl<'a> AuditRecord<'a> {
+    fn new(server: &EntityWorldMut, request: &'a Request, action: &'a str, status: u16) -> Self {
+        let query = request.query_string();
+        Self {
+            timestamp: rfc3339(SystemTime::now()),
+            action,
+            status,
+            client_ip: client_ip(request, server.resource::<RateLimiter>().trusted_proxies())
+                .map(|ip| ip.to_string()),
+            request_id: request.get_header(REQUEST_ID_HEADER),
+            user_agent: request.get_header("user-agent"),
+            query: (!query.is_empty()).then_some(query),
+            result: None,
+            suppressed: None,
+        }
+    }
+}
+
+/// Authorize the request, append the action planned by `plan` to the audit
+/// log and then apply it, responding with the JSON returned by `plan` or a
+/// `400` with its error.
+fn admin_action(
+    server: &mut EntityWorldMut,
+    request: &Request,
+    action: &str,
+    plan: impl FnOnce(&EntityWorldMut, &Request) -> Planned,
+) -> Response {
+    let Some(config) = server.resource::<ServerConfig>().admin().cloned() else {
+        return not_found(request);
+    };
+    if !config.is_authorized(request) {
+        let sampled = server
+            .resource_mut::<FailedAuthAudit>()
+            .sample(Instant::now());
+        if let Some(suppressed) = sampled {
+            warn!(
+                "Rejected unauthorized admin action {}, {} more since the last",
+                action, suppressed
+            );
+            let mut record = AuditRecord::new(server, request, action, 401);
+            record.suppressed = (suppressed > 0).then_some(suppressed);
+            // not synced, so rejected requests can't force a write to disk each
+            if let Err(err) = open_audit_log(&config).and_then(|mut audit_log| {
+                writeln!(audit_log, "{}", serde_json::to_string(&record)?)
+            }) {
+                error!(
+                    "Failed to write audit log {}: {}",
+                    config.audit_log().display(),
+                    err
+                );
+            }
+        }
+        return json_response(
+            http::StatusCode::UNAUTHORIZED.into(),
+            json!({ "error": "missing or invalid bearer token" }),
+        )
+        .with_header("www-authenticate", "Bearer realm=\"admin\"");
+    }
+
+    let (status, body, change) = match plan(server, request) {
+        Ok((body, change)) => (http::StatusCode::OK, body, change),
+        Err(reason) => (
+            http::StatusCode::BAD_REQUEST,
+            json!({ "error": reason }),
+            None,
+        ),
+    };
+
+    // reads could copy every visitor name into the logs
+    let result = (status.is_success() && *request.method() != HttpMethod::Get).then_some(&body);
+    let mut record = AuditRecord::new(server, request, action, status.as_u16());
+    record.result = result;
+    // synced first, so no action is applied without being recorded
+    if let Err(err) = append_audit_record(&config, &record) {
+        error!(
+            "Refusing admin action {}, failed to write audit log {}: {}",
+            action,
+            config.audit_log().display(),
+            err
+        );
+        return json_response(
+            http::StatusCode::SERVICE_UNAVAILABLE.into(),
+            json!({ "error": "audit log unavailable" }),
+        );
+    }
+
+    if let Some(change) = change {
+        change.apply(server);
+    }
+    if status.is_success() {
+        match result {
+            Some(result) => info!("Admin action {}: {}", action, result),
+            None => info!("Admin action {}", action),
+        }
+    }
+    json_response(status.into(), body)
+}
+
+/// Append the record to the audit log and sync it to disk.
+fn append_audit_record(config: &AdminConfig, record: &AuditRecord) -> std::io::Result<()> {
+    let line = serde_json::to_string(record)?;
+    let mut audit_log = open_audit_log(config)?;
+    writeln!(audit_log, "{line}")?;
+    audit_log.sync_data()
+}
+
+fn open_audit_log(config: &AdminConfig) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(config.audit_log())
+}
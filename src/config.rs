@@ -0,0 +1,420 @@
+//! Typed configuration loaded from the environment.
+//!
+//! Variables are read from the process environment and from a `.env` file
+//! in the working directory, which is where `cli.ts` uploads it on deploy.
+//! Variables already set in the environment take precedence over the file.
+//!
+//! | Key                         | Default      | Description                                 |
+//! |-----------------------------|--------------|---------------------------------------------|
+//! | `HELLO_HOST`                | `0.0.0.0`    | IPv4 address to listen on                   |
+//! | `HELLO_PORT`                | `8337`       | Port to listen on                           |
+//! | `HELLO_LOG_LEVEL`           | `info`       | `trace`, `debug`, `info`, `warn` or `error` |
+//! | `HELLO_ACCESS_LOG`          | `json`       | Access log format, `json`, `text` or `off`  |
+//! | `HELLO_STATE_PATH`          | `state.json` | File the visitor count is saved to          |
+//! | `HELLO_FLUSH_INTERVAL_SECS` | `5`          | How often the state is saved                |
+//! | `HELLO_PERSIST`             | `true`       | Whether to persist state at all             |
+//! | `HELLO_METRICS`             | `true`       | Whether to serve `/metrics`                 |
+//! | `HELLO_MAX_NAMES`           | `1024`       | Visitor names counted for `/leaderboard`    |
+//! | `HELLO_DRAIN_TIMEOUT_SECS`  | `10`         | How long to wait for requests on shutdown   |
+//! | `HELLO_RATE_LIMIT`          | `30/m+10`    | Greetings per client and burst, or `off`    |
+//! | `HELLO_TRUSTED_PROXIES`     |              | Comma separated proxy addresses or CIDRs    |
+//! | `HELLO_TLS_CERT`            |              | PEM certificate chain, enables HTTPS        |
+//! | `HELLO_TLS_KEY`             |              | PEM private key for `HELLO_TLS_CERT`        |
+//! | `HELLO_HTTP_REDIRECT_PORT`  |              | Port redirecting plain HTTP to HTTPS        |
+//! | `HELLO_ACME_DOMAINS`        |              | Comma separated domains, enables ACME       |
+//! | `HELLO_ACME_DIRECTORY`      |              | ACME directory url, Let's Encrypt if unset  |
+//! | `HELLO_ACME_CONTACT`        |              | Email for expiry notices                    |
+//! | `HELLO_ACME_CA_ROOT`        |              | PEM root to trust for the directory         |
+//! | `HELLO_STATIC_FILES`        | `true`       | Whether to serve the public directory       |
+//! | `HELLO_PUBLIC_DIR`          | `public`     | Directory static files are served from      |
+//! | `HELLO_COMPRESSION`         | `true`       | Whether to compress responses               |
+//! | `HELLO_COMPRESS_MIN_BYTES`  | `1024`       | Smallest response body worth compressing    |
+//! | `HELLO_LOCALES_DIR`         |              | Greeting catalogs, compiled in if unset     |
+//! | `HELLO_LIVE`                | `true`       | Whether to serve the live count WebSocket   |
+//! | `HELLO_LIVE_MAX_CLIENTS`    | `100`        | Live count clients connected at once        |
+//! | `HELLO_LIVE_HEARTBEAT_SECS` | `30`         | How often live count clients are pinged     |
+//! | `HELLO_EVENTS`              | `true`       | Whether to serve `/events`                  |
+//! | `HELLO_EVENTS_BUFFER`       | `100`        | Recent visits `/events` clients can resume  |
+//! | `HELLO_EVENTS_MAX_CLIENTS`  | `100`        | `/events` clients connected at once         |
+//! | `HELLO_ADMIN_TOKEN`         |              | Bearer token, enables the `/admin` routes   |
+//! | `HELLO_ADMIN_TOKEN_FILE`    |              | File to read `HELLO_ADMIN_TOKEN` from       |
+//! | `HELLO_AUDIT_LOG`           | `audit.log`  | File admin actions are appended to          |
+use crate::prelude::*;
+use beet::prelude::*;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Exit code for an invalid configuration, `EX_CONFIG` from `sysexits.h`.
+pub const CONFIG_EXIT_CODE: u8 = 78;
+
+/// A configuration value that could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// The offending environment variable, ie `HELLO_PORT`.
+    pub key: String,
+    /// The value that failed to parse.
+    pub value: String,
+    /// Why the value was rejected.
+    pub reason: String,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid value for {}: {:?} ({})",
+            self.key, self.value, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Everything needed to run the server, as loaded by [`AppConfig::from_env`].
+///
+/// Adding this as a plugin inserts its resources and adds the
+/// [`HelloLightsailPlugin`].
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    /// Host, port, routes and feature toggles.
+    pub server: ServerConfig,
+    /// Where the visitor count is persisted, only inserted if the
+    /// [`ServerConfig::persist`]s.
+    pub store: StateStore,
+    /// How long to drain connections on shutdown.
+    pub shutdown: ShutdownConfig,
+    /// How requests are logged.
+    pub access_log: AccessLog,
+    /// Per-client request limits.
+    pub rate_limit: RateLimiter,
+    /// Which responses are compressed.
+    pub compression: Compression,
+    /// The greeting in each language.
+    pub catalogs: Catalogs,
+    /// Limits for the live count WebSocket.
+    pub live: LiveCount,
+    /// How many visits `/events` clients can resume from.
+    pub events: VisitEvents,
+    /// The maximum log level, `RUST_LOG` takes precedence if set.
+    pub log_level: Level,
+}
+
+impl AppConfig {
+    /// Load the `.env` file in the working directory if present,
+    /// then read the configuration from the environment.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        dotenvy::from_path(".env").ok();
+        Self::from_vars(|key| std::env::var(key).ok())
+    }
+
+    /// Read the configuration using the given variable lookup.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut server = ServerConfig::default();
+        let mut store = StateStore::default();
+        let mut shutdown = ShutdownConfig::default();
+        let mut access_log = AccessLog::default();
+        let mut rate_limit = RateLimiter::default();
+        let mut compression = Compression::default();
+        let mut catalogs = Catalogs::default();
+        let mut live = LiveCount::default();
+        let mut events = VisitEvents::default();
+        let mut log_level = Level::INFO;
+
+        if let Some(host) = parse::<Ipv4Addr>(&var, "HELLO_HOST")? {
+            server = server.with_host(host.octets());
+        }
+        if let Some(port) = parse::<u16>(&var, "HELLO_PORT")? {
+            server = server.with_port(port);
+        }
+        if let Some(level) = parse::<Level>(&var, "HELLO_LOG_LEVEL")? {
+            log_level = level;
+        }
+        if let Some(format) = parse::<AccessLogFormat>(&var, "HELLO_ACCESS_LOG")? {
+            access_log = access_log.with_format(format);
+        }
+        if let Some(path) = var("HELLO_STATE_PATH") {
+            if path.trim().is_empty() {
+                return Err(invalid("HELLO_STATE_PATH", &path, "must not be empty"));
+            }
+            store = store.with_path(path);
+        }
+        if let Some(secs) = parse_nonzero::<u64>(&var, "HELLO_FLUSH_INTERVAL_SECS")? {
+            store = store.with_flush_interval(Duration::from_secs(secs));
+        }
+        if let Some(persist) = parse_bool(&var, "HELLO_PERSIST")? {
+            server = server.with_persist(persist);
+        }
+        if parse_bool(&var, "HELLO_METRICS")? == Some(false) {
+            server = server.without_route("/metrics");
+        }
+        if let Some(max_names) = parse_nonzero::<usize>(&var, "HELLO_MAX_NAMES")? {
+            server = server.with_max_names(max_names);
+        }
+        if let Some(secs) = parse::<u64>(&var, "HELLO_DRAIN_TIMEOUT_SECS")? {
+            shutdown = shutdown.with_drain_timeout(Duration::from_secs(secs));
+        }
+        if let Some(value) = var("HELLO_RATE_LIMIT") {
+            let limit = if value.trim().eq_ignore_ascii_case("off") {
+                None
+            } else {
+                let limit = value
+                    .parse::<RateLimit>()
+                    .map_err(|reason| invalid("HELLO_RATE_LIMIT", &value, reason))?;
+                Some(limit)
+            };
+            for pattern in GREETING_ROUTES {
+                rate_limit = match limit {
+                    Some(limit) => rate_limit.with_route_limit(pattern, limit),
+                    None => rate_limit.without_route_limit(pattern),
+                };
+            }
+        }
+        if let Some(value) = var("HELLO_TRUSTED_PROXIES") {
+            let proxies = value
+                .split(',')
+                .filter(|proxy| !proxy.trim().is_empty())
+                .map(IpRange::from_str)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|reason| invalid("HELLO_TRUSTED_PROXIES", &value, reason))?;
+            rate_limit = rate_limit.with_trusted_proxies(proxies);
+        }
+        if parse_bool(&var, "HELLO_STATIC_FILES")? != Some(false) {
+            let static_files = match var("HELLO_PUBLIC_DIR") {
+                Some(path) if path.trim().is_empty() => {
+                    return Err(invalid("HELLO_PUBLIC_DIR", &path, "must not be empty"));
+                }
+                Some(path) => StaticFiles::new(path.trim()),
+                #[cfg(feature = "embed-public")]
+                None => StaticFiles::embedded(),
+                #[cfg(not(feature = "embed-public"))]
+                None => StaticFiles::new("public"),
+            };
+            server = server.with_static_files(static_files);
+        }
+        if let Some(enabled) = parse_bool(&var, "HELLO_COMPRESSION")? {
+            compression = compression.with_enabled(enabled);
+        }
+        if let Some(min_size) = parse::<usize>(&var, "HELLO_COMPRESS_MIN_BYTES")? {
+            compression = compression.with_min_size(min_size);
+        }
+        if let Some(path) = var("HELLO_LOCALES_DIR") {
+            if path.trim().is_empty() {
+                return Err(invalid("HELLO_LOCALES_DIR", &path, "must not be empty"));
+            }
+            catalogs = Catalogs::load(Path::new(path.trim()))
+                .map_err(|reason| invalid("HELLO_LOCALES_DIR", &path, reason))?;
+        }
+        if parse_bool(&var, "HELLO_LIVE")? == Some(false) {
+            server = server.without_route(LIVE_COUNT_ROUTE);
+        }
+        if let Some(max_clients) = parse_nonzero::<usize>(&var, "HELLO_LIVE_MAX_CLIENTS")? {
+            live = live.with_max_clients(max_clients);
+        }
+        if let Some(secs) = parse_nonzero::<u64>(&var, "HELLO_LIVE_HEARTBEAT_SECS")? {
+            live = live.with_heartbeat(Duration::from_secs(secs));
+        }
+        if parse_bool(&var, "HELLO_EVENTS")? == Some(false) {
+            server = server.without_route(EVENTS_ROUTE);
+        }
+        if let Some(capacity) = parse_nonzero::<usize>(&var, "HELLO_EVENTS_BUFFER")? {
+            events = events.with_capacity(capacity);
+        }
+        if let Some(max_clients) = parse_nonzero::<usize>(&var, "HELLO_EVENTS_MAX_CLIENTS")? {
+            events = events.with_max_clients(max_clients);
+        }
+        match (var("HELLO_TLS_CERT"), var("HELLO_TLS_KEY")) {
+            (Some(cert), Some(key)) => server = server.with_tls(TlsConfig::new(cert, key)),
+            (Some(cert), None) => {
+                return Err(invalid("HELLO_TLS_CERT", &cert, "HELLO_TLS_KEY is not set"));
+            }
+            (None, Some(key)) => {
+                return Err(invalid("HELLO_TLS_KEY", &key, "HELLO_TLS_CERT is not set"));
+            }
+            (None, None) => {}
+        }
+        if let Some(value) = var("HELLO_ACME_DOMAINS") {
+            if server.tls().is_some() {
+                return Err(invalid(
+                    "HELLO_ACME_DOMAINS",
+                    &value,
+                    "cannot be combined with HELLO_TLS_CERT",
+                ));
+            }
+            let domains: Vec<&str> = value
+                .split(',')
+                .map(str::trim)
+                .filter(|domain| !domain.is_empty())
+                .collect();
+            if domains.is_empty() {
+                return Err(invalid("HELLO_ACME_DOMAINS", &value, "must not be empty"));
+            }
+            let mut acme = AcmeConfig::new(domains);
+            if let Some(url) = var("HELLO_ACME_DIRECTORY") {
+                acme = acme.with_directory_url(url.trim());
+            }
+            if let Some(contact) = var("HELLO_ACME_CONTACT") {
+                acme = acme.with_contact(contact.trim());
+            }
+            if let Some(path) = var("HELLO_ACME_CA_ROOT") {
+                acme = acme.with_ca_root(path.trim());
+            }
+            server = server.with_acme(acme);
+        }
+        if let Some(port) = parse::<u16>(&var, "HELLO_HTTP_REDIRECT_PORT")? {
+            if server.tls().is_none() {
+                return Err(invalid(
+                    "HELLO_HTTP_REDIRECT_PORT",
+                    &port.to_string(),
+                    "requires HELLO_TLS_CERT and HELLO_TLS_KEY or HELLO_ACME_DOMAINS",
+                ));
+            }
+            server = server.with_http_redirect(port);
+        }
+        let token = match (var("HELLO_ADMIN_TOKEN"), var("HELLO_ADMIN_TOKEN_FILE")) {
+            (Some(_), Some(path)) => {
+                return Err(invalid(
+                    "HELLO_ADMIN_TOKEN_FILE",
+                    &path,
+                    "cannot be combined with HELLO_ADMIN_TOKEN",
+                ));
+            }
+            (Some(token), None) => Some(token.trim().to_string()),
+            (None, Some(path)) => match std::fs::read_to_string(path.trim()) {
+                Ok(token) => Some(token.trim().to_string()),
+                Err(err) => return Err(invalid("HELLO_ADMIN_TOKEN_FILE", &path, err.to_string())),
+            },
+            (None, None) => None,
+        };
+        if let Some(token) = token {
+            if token.chars().count() < MIN_ADMIN_TOKEN_CHARS {
+                return Err(invalid(
+                    "HELLO_ADMIN_TOKEN",
+                    "<redacted>",
+                    format!("must be at least {MIN_ADMIN_TOKEN_CHARS} characters"),
+                ));
+            }
+            let mut admin = AdminConfig::new(&token);
+            if let Some(path) = var("HELLO_AUDIT_LOG") {
+                if path.trim().is_empty() {
+                    return Err(invalid("HELLO_AUDIT_LOG", &path, "must not be empty"));
+                }
+                admin = admin.with_audit_log(path.trim());
+            }
+            server = server.with_admin(admin);
+        } else if let Some(path) = var("HELLO_AUDIT_LOG") {
+            return Err(invalid(
+                "HELLO_AUDIT_LOG",
+                &path,
+                "requires HELLO_ADMIN_TOKEN or HELLO_ADMIN_TOKEN_FILE",
+            ));
+        }
+
+        Ok(Self {
+            server,
+            store,
+            shutdown,
+            access_log,
+            rate_limit,
+            compression,
+            catalogs,
+            live,
+            events,
+            log_level,
+        })
+    }
+
+    /// A [`LogPlugin`] writing the configured level, which can be changed
+    /// at runtime with [`set_log_level`].
+    ///
+    /// This sets the global [`log_level`], or lets `RUST_LOG` decide if set.
+    pub fn log_plugin(&self) -> LogPlugin {
+        let level = if std::env::var_os("RUST_LOG").is_some() {
+            Level::TRACE
+        } else {
+            self.log_level
+        };
+        set_log_level(level);
+        LogPlugin {
+            // filtered by the fmt layer instead, so it can change at runtime
+            level: Level::TRACE,
+            fmt_layer: runtime_level_fmt_layer,
+            ..default()
+        }
+    }
+}
+
+impl Plugin for AppConfig {
+    fn build(&self, app: &mut App) {
+        // without a store the readiness probe doesn't check the state path
+        if self.server.persist() {
+            app.insert_resource(self.store.clone());
+        }
+        app.insert_resource(self.server.clone())
+            .insert_resource(self.shutdown.clone())
+            .insert_resource(self.access_log.clone())
+            .insert_resource(self.rate_limit.clone())
+            .insert_resource(self.compression.clone())
+            .insert_resource(self.catalogs.clone())
+            .insert_resource(self.live.clone())
+            .insert_resource(self.events.clone())
+            .add_plugins(HelloLightsailPlugin);
+    }
+}
+
+fn invalid(key: &str, value: &str, reason: impl Into<String>) -> ConfigError {
+    ConfigError {
+        key: key.into(),
+        value: value.into(),
+        reason: reason.into(),
+    }
+}
+
+fn parse<T>(var: &impl Fn(&str) -> Option<String>, key: &str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match var(key) {
+        Some(value) => value
+            .trim()
+            .parse::<T>()
+            .map(Some)
+            .map_err(|err| invalid(key, &value, err.to_string())),
+        None => Ok(None),
+    }
+}
+
+/// Like [`parse`], but rejecting zero, which would silently disable the
+/// feature rather than size it. Turn features off with their own toggle.
+fn parse_nonzero<T>(
+    var: &impl Fn(&str) -> Option<String>,
+    key: &str,
+) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr + Default + PartialEq,
+    T::Err: std::fmt::Display,
+{
+    match parse::<T>(var, key)? {
+        Some(value) if value == T::default() => Err(invalid(
+            key,
+            &var(key).unwrap_or_default(),
+            "must be at least 1",
+        )),
+        value => Ok(value),
+    }
+}
+
+fn parse_bool(
+    var: &impl Fn(&str) -> Option<String>,
+    key: &str,
+) -> Result<Option<bool>, ConfigError> {
+    match var(key) {
+        Some(value) => match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(Some(true)),
+            "0" | "false" | "no" | "off" => Ok(Some(false)),
+            _ => Err(invalid(key, &value, "expected true or false")),
+        },
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,272 @@
+//! Visits as a stream of server-sent events, for clients without WebSockets.
+//!
+//! `GET /events` answers with `text/event-stream` and then sends a `visit`
+//! event every time the greeting counts a visit, with the same JSON as the
+//! [`LiveCount`]:
+//!
+//! ```text
+//! id: 7
+//! event: visit
+//! data: {"count":42,"name":"pete"}
+//! ```
+//!
+//! The most recent visits are kept in a ring buffer, so a client that
+//! reconnects with the `Last-Event-ID` header is first sent the visits it
+//! missed, as far back as the buffer goes. Ids count up from 1 each time
+//! the server starts, so an id newer than any seen means the client last
+//! connected to an earlier process, and gets everything buffered.
+//!
+//! Like the [`LiveCount`] WebSocket, [`visit_events`] only answers the
+//! request, marking it with [`EVENT_STREAM_MARKER`], and the
+//! [`HttpListener`] sends the [`VisitEvents::stream`] as its body until the
+//! client disconnects or the server shuts down. A client that falls behind
+//! is disconnected, to resume from its last event. At most
+//! [`VisitEvents::max_clients`] are streamed to at once, with a
+//! `503 Service Unavailable` for the rest.
+use crate::prelude::*;
+use beet::exports::async_channel;
+use beet::exports::futures_lite;
+use beet::exports::futures_lite::Stream;
+use beet::exports::futures_lite::StreamExt;
+use beet::exports::http;
+use beet::prelude::*;
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+/// The route serving the visit event stream.
+pub const EVENTS_ROUTE: &str = "/events";
+
+/// The content type of the visit event stream.
+pub const EVENT_STREAM_TYPE: &str = "text/event-stream";
+
+/// Set by [`visit_events`] to the id the stream resumes after, if any. The
+/// [`HttpListener`] removes it and streams the visits from there.
+pub const EVENT_STREAM_MARKER: &str = "x-visit-events-after";
+
+/// How long clients wait before reconnecting, sent as the stream's `retry`.
+const RETRY: Duration = Duration::from_secs(3);
+
+/// How often a comment is sent on an idle stream, so proxies and clients
+/// don't time it out.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Events queued for a client before it is disconnected as too slow.
+const CLIENT_QUEUE: usize = 64;
+
+/// Recent visits and the clients streaming new ones, shared by every clone.
+#[derive(Debug, Clone, Resource)]
+pub struct VisitEvents {
+    capacity: usize,
+    max_clients: usize,
+    log: Arc<Mutex<VisitLog>>,
+    active: Arc<AtomicUsize>,
+}
+
+impl Default for VisitEvents {
+    fn default() -> Self {
+        Self {
+            capacity: 100,
+            max_clients: 100,
+            log: default(),
+            active: default(),
+        }
+    }
+}
+
+/// A slot for a client, released on drop.
+struct EventsClient {
+    events: async_channel::Receiver<Bytes>,
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for EventsClient {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Default)]
+struct VisitLog {
+    /// The id of the last visit, `0` before the first.
+    last_id: u64,
+    /// The most recent visits by id, formatted as events.
+    recent: VecDeque<(u64, Bytes)>,
+    clients: Vec<async_channel::Sender<Bytes>>,
+}
+
+impl VisitLog {
+    /// The buffered events after `last_event_id`, or all of them if the id
+    /// is from a previous process.
+    fn since(&self, last_event_id: Option<u64>) -> Vec<Bytes> {
+        let Some(last_event_id) = last_event_id else {
+            return Vec::new();
+        };
+        let after = if last_event_id > self.last_id {
+            0
+        } else {
+            last_event_id
+        };
+        self.recent
+            .iter()
+            .filter(|(id, _)| *id > after)
+            .map(|(_, event)| event.clone())
+            .collect()
+    }
+}
+
+impl VisitEvents {
+    /// Keep this many recent visits to resume from, defaults to `100`.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Allow at most this many clients at once, defaults to `100`.
+    pub fn with_max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = max_clients;
+        self
+    }
+
+    /// How many recent visits are kept.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The maximum number of clients.
+    pub fn max_clients(&self) -> usize {
+        self.max_clients
+    }
+
+    /// The number of clients streaming visits.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Whether no more clients can connect.
+    pub fn is_full(&self) -> bool {
+        self.active() >= self.max_clients
+    }
+
+    /// Record a visit and send it to every client, returning its event id.
+    pub fn publish(&self, visit: &CountUpdate) -> u64 {
+        let mut log = self.log.lock().unwrap();
+        log.last_id += 1;
+        let id = log.last_id;
+        let event = Bytes::from(format!(
+            "id: {id}\nevent: visit\ndata: {}\n\n",
+            serde_json::to_string(visit).unwrap()
+        ));
+        if self.capacity > 0 {
+            if log.recent.len() >= self.capacity {
+                log.recent.pop_front();
+            }
+            log.recent.push_back((id, event.clone()));
+        }
+        // a full queue drops the client, which resumes from its last event
+        log.clients
+            .retain(|client| client.try_send(event.clone()).is_ok());
+        id
+    }
+
+    /// Take a slot for a client with the buffered visits after
+    /// `last_event_id`, `None` if there are already
+    /// [`VisitEvents::max_clients`].
+    fn join(&self, last_event_id: Option<u64>) -> Option<(EventsClient, Vec<Bytes>)> {
+        self.active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                (active < self.max_clients).then_some(active + 1)
+            })
+            .ok()?;
+        let (sender, events) = async_channel::bounded(CLIENT_QUEUE);
+        let client = EventsClient {
+            events,
+            active: self.active.clone(),
+        };
+        // subscribed while locked, so no visit is missed or sent twice
+        let mut log = self.log.lock().unwrap();
+        log.clients.retain(|client| !client.is_closed());
+        log.clients.push(sender);
+        Some((client, log.since(last_event_id)))
+    }
+
+    /// The stream resuming after `last_event_id`, which ends when the
+    /// [`Connections`] stop.
+    ///
+    /// It starts with the `retry` interval and the buffered visits the
+    /// client missed, then sends each new visit. If the
+    /// [`VisitEvents::max_clients`] are already streaming it ends after the
+    /// `retry`, for the client to reconnect later.
+    pub fn stream(
+        &self,
+        last_event_id: Option<u64>,
+        connections: Connections,
+    ) -> impl Stream<Item = Bytes> + Send + 'static {
+        // the handler checked too, but another client may have joined since
+        let (client, missed) = match self.join(last_event_id) {
+            Some((client, missed)) => (Some(client), missed),
+            None => (None, Vec::new()),
+        };
+        let start = stream_start(missed);
+        let keep_alive = async_io::Timer::interval(KEEP_ALIVE);
+        let rest = futures_lite::stream::unfold(
+            (client, keep_alive, connections),
+            |(client, mut keep_alive, connections)| async move {
+                let events = &client.as_ref()?.events;
+                let next = futures_lite::future::or(
+                    futures_lite::future::or(async { events.recv().await.ok() }, async {
+                        keep_alive.next().await;
+                        Some(Bytes::from_static(b": keep-alive\n\n"))
+                    }),
+                    async {
+                        connections.stopped().await;
+                        None
+                    },
+                )
+                .await?;
+                Some((next, (client, keep_alive, connections)))
+            },
+        );
+        futures_lite::stream::once(start).chain(rest)
+    }
+}
+
+fn stream_start(events: Vec<Bytes>) -> Bytes {
+    let mut start = format!("retry: {}\n\n", RETRY.as_millis()).into_bytes();
+    for event in events {
+        start.extend_from_slice(&event);
+    }
+    start.into()
+}
+
+/// The id of a `Last-Event-ID` header, `None` if it isn't one of ours.
+pub fn last_event_id(header: Option<&str>) -> Option<u64> {
+    header?.trim().parse().ok()
+}
+
+/// `GET /events`, stream the [`VisitEvents`].
+///
+/// The response has no body of its own, the [`HttpListener`] streams the
+/// visits after `Last-Event-ID` as marked by [`EVENT_STREAM_MARKER`].
+/// While the stream is full it gets `503 Service Unavailable`.
+pub fn visit_events(server: &mut EntityWorldMut, request: Request) -> Response {
+    if server.resource::<VisitEvents>().is_full() {
+        return Response::from_status_body(
+            http::StatusCode::SERVICE_UNAVAILABLE.into(),
+            "Service Unavailable: too many event stream clients",
+            "text/plain",
+        )
+        .with_header("retry-after", "30");
+    }
+    let after = last_event_id(request.get_header("last-event-id"))
+        .map(|id| id.to_string())
+        .unwrap_or_default();
+    Response::ok_body("", EVENT_STREAM_TYPE)
+        .with_header("cache-control", "no-store")
+        // or nginx would buffer the stream
+        .with_header("x-accel-buffering", "no")
+        .with_header(EVENT_STREAM_MARKER, &after)
+}
@@ -0,0 +1,154 @@
+//! The greeting served at the root route.
+use crate::prelude::*;
+use beet::exports::http;
+use beet::prelude::*;
+use fluent_bundle::FluentArgs;
+use serde::Serialize;
+use unic_langid::LanguageIdentifier;
+
+/// The default route patterns served by [`greeting`], which count visits.
+pub const GREETING_ROUTES: [&str; 2] = ["/", "/hello/{name}"];
+
+/// Representations of the [`Greeting`], text first for `curl`.
+const GREETING_TYPES: [MediaType; 3] = [MediaType::Text, MediaType::Json, MediaType::Html];
+
+/// Stands in for the parameter name while formatting the hint.
+const PARAM_MARKER: &str = "\u{fffc}";
+
+/// A single greeting, rendered as text, JSON or HTML.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Greeting {
+    /// Who is being greeted, `world` in the greeting's locale by default.
+    pub name: String,
+    /// The visitor count including this visit.
+    pub visitor_number: u32,
+    /// Visits by this name including this one, `None` if no name was given.
+    pub visits: Option<u32>,
+    /// The locale the greeting is in, ie `en`.
+    pub locale: LanguageIdentifier,
+}
+
+/// The sentences of a [`Greeting`] in its locale.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GreetingText {
+    /// `hello pete`, or `welcome back, pete, that makes 3 visits` for a
+    /// returning name.
+    pub salutation: String,
+    /// `you are the 3rd visitor`.
+    pub visitor: String,
+    /// How to be greeted by name, split around the parameter so each
+    /// representation can mark it up.
+    pub hint: (String, String),
+}
+
+impl Greeting {
+    /// Render in the given representation.
+    pub fn render(&self, media_type: MediaType, catalogs: &Catalogs) -> String {
+        match media_type {
+            MediaType::Text => self.text(&self.localize(catalogs)),
+            MediaType::Json => serde_json::to_string(self).unwrap(),
+            MediaType::Html => greeting_page(self, &self.localize(catalogs)),
+        }
+    }
+
+    /// The sentences in the greeting's locale.
+    pub fn localize(&self, catalogs: &Catalogs) -> GreetingText {
+        let mut args = FluentArgs::new();
+        args.set("name", self.name.as_str());
+        args.set("number", self.visitor_number);
+        args.set("param", PARAM_MARKER);
+        let salutation = match self.visits {
+            Some(visits) if visits > 1 => {
+                args.set("visits", visits);
+                catalogs.format(&self.locale, "welcome-back", &args)
+            }
+            _ => catalogs.format(&self.locale, "hello", &args),
+        };
+        let hint = catalogs.format(&self.locale, "name-hint", &args);
+        let hint = match hint.split_once(PARAM_MARKER) {
+            Some((before, after)) => (before.to_string(), after.to_string()),
+            None => (hint, String::new()),
+        };
+        GreetingText {
+            salutation,
+            visitor: catalogs.format(&self.locale, "visitor-number", &args),
+            hint,
+        }
+    }
+
+    fn text(&self, text: &GreetingText) -> String {
+        format!(
+            r#"
+{}
+{}
+
+{}'name'{}
+"#,
+            text.salutation, text.visitor, text.hint.0, text.hint.1
+        )
+    }
+}
+
+/// Greets the visitor by the `name` parameter and increments the visitor
+/// [`Count`] and the visits for that name in [`NameCounts`], publishing the
+/// new count to the [`LiveCount`] and [`VisitEvents`].
+///
+/// A blank name greets the world, and one that is not a valid
+/// [`VisitorName`] gets a `400 Bad Request` saying why, without counting
+/// the visit.
+///
+/// Responds with text, JSON or HTML according to the `Accept` header or the
+/// `?format=` parameter, see [`negotiate`], in the language picked by
+/// [`Catalogs::negotiate`].
+pub fn greeting(server: &mut EntityWorldMut, request: Request) -> Response {
+    // a visit only counts if we can answer it
+    let Some(media_type) = negotiate(&request, &GREETING_TYPES) else {
+        return not_acceptable(&GREETING_TYPES).with_header("vary", "accept");
+    };
+    let catalogs = server.resource::<Catalogs>().clone();
+    let locale = catalogs.negotiate(&request).clone();
+    let name = match request
+        .get_param("name")
+        .map(decode_param)
+        .map(|name| VisitorName::parse(&name))
+    {
+        None | Some(Err(NameError::Blank)) => None,
+        Some(Ok(name)) => Some(name),
+        Some(Err(err)) => {
+            return error_response(&request, http::StatusCode::BAD_REQUEST, &err.to_string());
+        }
+    };
+
+    let visits = match (&name, server.get_mut::<NameCounts>()) {
+        (Some(name), Some(mut counts)) => Some(counts.visit(&name.key())),
+        _ => None,
+    };
+
+    // increment visitor count, which an admin may have set to the maximum
+    let mut count = server.get_mut::<Count>().unwrap();
+    count.0 = count.0.saturating_add(1);
+    let count = count.0;
+    let update = CountUpdate {
+        count,
+        name: name.as_ref().map(ToString::to_string),
+    };
+    server.resource::<VisitEvents>().publish(&update);
+    server.resource::<LiveCount>().publish(update);
+
+    let greeting = Greeting {
+        name: match name {
+            Some(name) => name.to_string(),
+            None => catalogs.format(&locale, "world", &FluentArgs::new()),
+        },
+        visitor_number: count,
+        visits,
+        locale,
+    };
+    let response = Response::ok_body(
+        greeting.render(media_type, &catalogs),
+        media_type.content_type(),
+    )
+    .with_header("vary", "accept")
+    .with_header("content-language", &greeting.locale.to_string());
+    with_vary(response, "accept-language")
+}
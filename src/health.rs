@@ -0,0 +1,49 @@
+//! Liveness and readiness probes.
+//!
+//! Neither route touches the visitor [`Count`], so uptime checkers and
+//! the deploy script can poll them freely.
+use crate::prelude::*;
+use beet::prelude::*;
+use serde_json::json;
+
+/// `GET /healthz`, responds `200` as long as the process is serving requests.
+pub fn healthz(_server: &mut EntityWorldMut, _request: Request) -> Response {
+    json_response(StatusCode::Ok, json!({ "status": "ok" }))
+}
+
+/// `GET /readyz`, responds `200` once the persisted state has been loaded
+/// and the state directory is writable, otherwise `503`.
+///
+/// If persistence is disabled there is no [`StateStore`], so both checks
+/// pass trivially.
+pub fn readyz(server: &mut EntityWorldMut, _request: Request) -> Response {
+    let state_loaded = server
+        .get_resource::<StoreStatus>()
+        .map(|status| status.loaded())
+        .unwrap_or(true);
+    let storage_writable = match server.get_resource::<StateStore>() {
+        Some(store) => match store.check_writable() {
+            Ok(()) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        },
+        None => Ok(()),
+    };
+
+    let ready = state_loaded && storage_writable.is_ok();
+    let (status, label) = if ready {
+        (StatusCode::Ok, "ready")
+    } else {
+        (StatusCode::ServiceUnavailable, "not_ready")
+    };
+    json_response(
+        status,
+        json!({
+            "status": label,
+            "checks": {
+                "state_loaded": state_loaded,
+                "storage_writable": storage_writable.is_ok(),
+            },
+            "error": storage_writable.err(),
+        }),
+    )
+}
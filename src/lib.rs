@@ -1 +1,61 @@
+//! Server plumbing for the hello-lightsail deployment.
+//!
+//! Add the [`HelloLightsailPlugin`](prelude::HelloLightsailPlugin) to an
+//! [`App`](beet::prelude::App) to serve the visitor greeting.
+mod access_log;
+mod acme;
+mod activation;
+mod admin;
+mod compression;
+mod config;
+mod events;
+mod greeting;
+mod health;
+mod listener;
+mod live;
+mod locales;
+mod logging;
+mod metrics;
+mod names;
+mod negotiate;
+mod notify;
+mod rate_limit;
+mod request_id;
+mod router;
+mod server;
+mod shutdown;
+mod static_files;
+mod store;
+mod templates;
+mod test_server;
+mod tls;
 
+pub mod prelude {
+    pub use crate::access_log::*;
+    pub use crate::acme::*;
+    pub use crate::activation::*;
+    pub use crate::admin::*;
+    pub use crate::compression::*;
+    pub use crate::config::*;
+    pub use crate::events::*;
+    pub use crate::greeting::*;
+    pub use crate::health::*;
+    pub use crate::listener::*;
+    pub use crate::live::*;
+    pub use crate::locales::*;
+    pub use crate::logging::*;
+    pub use crate::metrics::*;
+    pub use crate::names::*;
+    pub use crate::negotiate::*;
+    pub use crate::notify::*;
+    pub use crate::rate_limit::*;
+    pub use crate::request_id::*;
+    pub use crate::router::*;
+    pub use crate::server::*;
+    pub use crate::shutdown::*;
+    pub use crate::static_files::*;
+    pub use crate::store::*;
+    pub use crate::templates::*;
+    pub use crate::test_server::*;
+    pub use crate::tls::*;
+}
@@ -0,0 +1,530 @@
+//! An HTTP/1 listener that can be drained.
+//!
+//! This serves the exchange handler of its entity like beet's [`HttpServer`],
+//! but keeps track of open [`Connections`] so that on shutdown it can stop
+//! accepting and let in-flight requests finish. With a [`TlsCertificate`]
+//! it serves HTTPS instead.
+//!
+//! A WebSocket handshake the handler answers with `101 Switching Protocols`
+//! is handed to the [`LiveCount`], see [`live_count`], a
+//! `text/event-stream` response is continued by the [`VisitEvents`], see
+//! [`visit_events`], and a large static file is read from disk as it is
+//! sent, see [`static_file`].
+use crate::prelude::*;
+use beet::exports::SendWrapper;
+use beet::exports::async_channel;
+use beet::exports::bevy::tasks::IoTaskPool;
+use beet::exports::futures_lite;
+use beet::exports::http;
+use beet::exports::http_body_util;
+use beet::prelude::*;
+use bytes::Bytes;
+use futures_rustls::TlsAcceptor;
+use http_body_util::BodyExt;
+use http_body_util::Full;
+use http_body_util::StreamBody;
+use http_body_util::combinators::BoxBody;
+use hyper::body::Frame;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::pin::Pin;
+use std::pin::pin;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+
+/// Serves the exchange handler of this entity over HTTP/1.
+///
+/// If systemd passed a listening socket, see [`InheritedSockets`], that is
+/// used instead of binding the address.
+#[derive(Debug, Clone, Component)]
+#[component(on_add = on_add)]
+pub struct HttpListener {
+    addr: SocketAddr,
+    tls: Option<TlsCertificate>,
+}
+
+impl HttpListener {
+    /// Listen on the given address, ie `([0, 0, 0, 0], 8337)`.
+    pub fn new(addr: impl Into<SocketAddr>) -> Self {
+        Self {
+            addr: addr.into(),
+            tls: None,
+        }
+    }
+
+    /// Serve HTTPS with the certificate.
+    pub fn with_tls(mut self, certificate: TlsCertificate) -> Self {
+        self.tls = Some(certificate);
+        self
+    }
+
+    /// The address this listener binds to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The certificate if serving HTTPS.
+    pub fn tls(&self) -> Option<&TlsCertificate> {
+        self.tls.as_ref()
+    }
+}
+
+/// How long a client has to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Set by the [`HttpListener`] on every request to the address of the
+/// connected client, replacing any value sent by the client itself.
+pub const PEER_ADDR_HEADER: &str = "x-peer-addr";
+
+/// The address of the connected client, as set by the [`HttpListener`].
+///
+/// Behind a reverse proxy this is the address of the proxy.
+pub fn peer_addr(request: &Request) -> Option<SocketAddr> {
+    request.get_header(PEER_ADDR_HEADER)?.parse().ok()
+}
+
+/// Added to an [`HttpListener`] entity once it has bound,
+/// with the local address it is listening on.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct Listening(pub SocketAddr);
+
+fn on_add(mut world: DeferredWorld, cx: HookContext) {
+    world.commands().init_resource::<Connections>();
+    world.commands().init_resource::<InheritedSockets>();
+    world
+        .commands()
+        .run_system_cached_with(start_listener, cx.entity);
+}
+
+/// Counts open connections and tells them when to stop.
+///
+/// Shared between every [`HttpListener`] in the app.
+#[derive(Debug, Clone, Resource)]
+pub struct Connections {
+    active: Arc<AtomicUsize>,
+    stop_tx: async_channel::Sender<()>,
+    stop_rx: async_channel::Receiver<()>,
+}
+
+impl Default for Connections {
+    fn default() -> Self {
+        // nothing is ever sent, closing the channel wakes every receiver
+        let (stop_tx, stop_rx) = async_channel::bounded(1);
+        Self {
+            active: default(),
+            stop_tx,
+            stop_rx,
+        }
+    }
+}
+
+impl Connections {
+    /// The number of connections currently open.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Stop accepting new connections and close open ones once their
+    /// in-flight request has been answered.
+    pub fn stop(&self) {
+        self.stop_tx.close();
+    }
+
+    /// Whether [`Connections::stop`] has been called.
+    pub fn is_stopped(&self) -> bool {
+        self.stop_tx.is_closed()
+    }
+
+    /// Resolves once [`Connections::stop`] has been called.
+    pub async fn stopped(&self) {
+        self.stop_rx.recv().await.ok();
+    }
+
+    fn open(&self) -> ConnectionGuard {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        ConnectionGuard(self.active.clone())
+    }
+}
+
+/// Decrements the active count when the connection task ends.
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn start_listener(
+    In(entity): In<Entity>,
+    query: Query<&HttpListener>,
+    connections: Res<Connections>,
+    (live, events): (Option<Res<LiveCount>>, Option<Res<VisitEvents>>),
+    channel: Res<AsyncChannel>,
+    mut inherited: ResMut<InheritedSockets>,
+    mut commands: Commands,
+) -> Result {
+    let http_listener = query.get(entity)?;
+    let addr = http_listener.addr;
+    let acceptor = http_listener
+        .tls
+        .as_ref()
+        .map(TlsCertificate::acceptor)
+        .transpose()?;
+    let scheme = if acceptor.is_some() { "https" } else { "http" };
+    let listener = match inherited.take() {
+        Some(listener) => {
+            let inherited_addr = listener.local_addr()?;
+            if !InheritedSockets::serves(addr, inherited_addr) {
+                // the socket unit decides where clients connect
+                warn!(
+                    "Inherited socket is bound to {}, not the configured {}, \
+                     update the socket unit or HELLO_HOST and HELLO_PORT",
+                    inherited_addr, addr
+                );
+            }
+            async_io::Async::new(listener)?
+        }
+        None => async_io::Async::<TcpListener>::bind(addr)
+            .map_err(|err| bevyhow!("Failed to bind to {}: {}", addr, err))?,
+    };
+    // differs from `addr` when inherited or binding to port 0
+    let addr = listener.get_ref().local_addr()?;
+    info!("Server listening on {}://{}", scheme, addr);
+    commands.entity(entity).insert(Listening(addr));
+
+    let connections = connections.clone();
+    let streams = Streams {
+        live: live.map(|live| live.clone()),
+        events: events.map(|events| events.clone()),
+    };
+    let world = channel.world();
+    // spawned directly rather than with `AsyncCommands` so that a stopped
+    // listener sends nothing back to a world that may have already exited
+    IoTaskPool::get()
+        .spawn(async move {
+            loop {
+                let accepted =
+                    futures_lite::future::or(async { Some(listener.accept().await) }, async {
+                        connections.stopped().await;
+                        None
+                    })
+                    .await;
+                let (stream, peer) = match accepted {
+                    Some(Ok(accepted)) => accepted,
+                    Some(Err(err)) => {
+                        warn!("Failed to accept connection: {}", err);
+                        continue;
+                    }
+                    None => break,
+                };
+                trace!("New connection from: {}", peer);
+                serve_connection(
+                    world.clone(),
+                    entity,
+                    stream,
+                    peer,
+                    acceptor.clone(),
+                    connections.clone(),
+                    streams.clone(),
+                );
+            }
+            // dropping the listener closes the socket
+            info!("Stopped accepting connections on {}://{}", scheme, addr);
+        })
+        .detach();
+    Ok(())
+}
+
+fn serve_connection(
+    world: AsyncWorld,
+    entity: Entity,
+    stream: async_io::Async<std::net::TcpStream>,
+    peer: SocketAddr,
+    acceptor: Option<TlsAcceptor>,
+    connections: Connections,
+    streams: Streams,
+) {
+    let guard = connections.open();
+    IoTaskPool::get()
+        .spawn(async move {
+            let _guard = guard;
+            let Some(acceptor) = acceptor else {
+                serve_http(world, entity, stream, peer, &connections, streams).await;
+                return;
+            };
+            let handshake =
+                futures_lite::future::or(async { Some(acceptor.accept(stream).await) }, async {
+                    async_io::Timer::after(TLS_HANDSHAKE_TIMEOUT).await;
+                    None
+                });
+            match handshake.await {
+                Some(Ok(stream)) => {
+                    serve_http(world, entity, stream, peer, &connections, streams).await
+                }
+                Some(Err(err)) => debug!("TLS handshake with {} failed: {}", peer, err),
+                None => trace!("TLS handshake with {} timed out", peer),
+            }
+        })
+        .detach();
+}
+
+async fn serve_http<S>(
+    world: AsyncWorld,
+    entity: Entity,
+    stream: S,
+    peer: SocketAddr,
+    connections: &Connections,
+    streams: Streams,
+) where
+    S: futures_lite::AsyncRead + futures_lite::AsyncWrite + Unpin + Send + 'static,
+{
+    let service_connections = connections.clone();
+    let service = service_fn(move |mut req: hyper::Request<Incoming>| {
+        let world = world.clone();
+        let connections = service_connections.clone();
+        let streams = streams.clone();
+        async move {
+            let on_upgrade = req
+                .headers()
+                .contains_key(http::header::UPGRADE)
+                .then(|| hyper::upgrade::on(&mut req));
+            let req = hyper_to_request(req, peer);
+            let mut res = response_to_hyper(world.entity(entity).exchange(req).await);
+            if res.status() == http::StatusCode::SWITCHING_PROTOCOLS
+                && let (Some(on_upgrade), Some(live)) = (on_upgrade, streams.live)
+            {
+                serve_upgraded(on_upgrade, peer, connections, live);
+            } else if let Some(after) = res.headers_mut().remove(EVENT_STREAM_MARKER)
+                && let Some(events) = streams.events
+            {
+                let after = last_event_id(after.to_str().ok());
+                let frames = events
+                    .stream(after, connections)
+                    .map(|bytes| Ok(Frame::data(bytes)));
+                res.headers_mut().remove(http::header::CONTENT_LENGTH);
+                *res.body_mut() = BodyExt::boxed(StreamBody::new(frames));
+            } else if let Some(file) = res.headers_mut().remove(STATIC_FILE_MARKER)
+                && let Some(file) = file.to_str().ok().and_then(StaticStream::from_marker)
+            {
+                res.headers_mut()
+                    .insert(http::header::CONTENT_LENGTH, file.content_length().into());
+                let frames = file.into_stream().map(|chunk| chunk.map(Frame::data));
+                *res.body_mut() = BodyExt::boxed(StreamBody::new(frames));
+            }
+            Ok::<_, Infallible>(res)
+        }
+    });
+
+    let conn = http1::Builder::new()
+        .timer(ListenerTimer)
+        .header_read_timeout(Duration::from_secs(2))
+        .serve_connection(ListenerIo(stream), service)
+        .with_upgrades();
+    let mut conn = pin!(conn);
+    let mut stopped = pin!(connections.stopped());
+    let mut draining = false;
+
+    let result = std::future::poll_fn(|cx| {
+        if !draining && stopped.as_mut().poll(cx).is_ready() {
+            // finish the in-flight request, then close
+            draining = true;
+            conn.as_mut().graceful_shutdown();
+        }
+        conn.as_mut().poll(cx)
+    })
+    .await;
+
+    if let Err(err) = result {
+        if err.is_timeout() {
+            trace!("Connection closed due to header timeout");
+        } else {
+            debug!("Error serving connection: {:?}", err);
+        }
+    }
+}
+
+/// The streams the handler's response can be continued with.
+#[derive(Clone, Default)]
+struct Streams {
+    live: Option<LiveCount>,
+    events: Option<VisitEvents>,
+}
+
+/// Serve the [`LiveCount`] on the connection once hyper has sent the
+/// `101` and handed it over, counting it as open until it closes.
+fn serve_upgraded(
+    on_upgrade: hyper::upgrade::OnUpgrade,
+    peer: SocketAddr,
+    connections: Connections,
+    live: LiveCount,
+) {
+    let guard = connections.open();
+    IoTaskPool::get()
+        .spawn(async move {
+            let _guard = guard;
+            match on_upgrade.await {
+                Ok(upgraded) => live.serve(UpgradedIo(upgraded), &connections).await,
+                Err(err) => debug!("Failed to upgrade connection from {}: {}", peer, err),
+            }
+        })
+        .detach();
+}
+
+fn hyper_to_request(req: hyper::Request<Incoming>, peer: SocketAddr) -> Request {
+    let (mut parts, body) = req.into_parts();
+    parts.headers.insert(
+        PEER_ADDR_HEADER,
+        http::HeaderValue::from_str(&peer.to_string()).expect("socket addresses are ascii"),
+    );
+    let stream = http_body_util::BodyStream::new(body).map(|result| match result {
+        Ok(frame) => frame
+            .into_data()
+            .map_err(|_| bevyhow!("Failed to convert frame to data")),
+        Err(err) => Err(bevyhow!("Body stream error: {:?}", err)),
+    });
+    let body = Body::Stream(SendWrapper::new(Box::pin(stream)));
+    Request::from_parts(RequestParts::from(parts), body)
+}
+
+fn response_to_hyper(res: Response) -> hyper::Response<BoxBody<Bytes, std::io::Error>> {
+    let (parts, body) = res.into_parts();
+    let parts: http::response::Parts = parts.try_into().unwrap_or_else(|_| {
+        http::Response::builder()
+            .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0
+    });
+
+    match body {
+        Body::Bytes(bytes) => {
+            let body = Full::new(bytes).map_err(|never| match never {}).boxed();
+            hyper::Response::from_parts(parts, body)
+        }
+        Body::Stream(stream) => {
+            let frames = stream.take().map(|result| {
+                result
+                    .map(Frame::data)
+                    .map_err(|err| std::io::Error::other(err.to_string()))
+            });
+            hyper::Response::from_parts(parts, BodyExt::boxed(StreamBody::new(frames)))
+        }
+    }
+}
+
+/// Adapts an async-io stream to hyper's io traits.
+struct ListenerIo<S>(S);
+
+impl<S> hyper::rt::Read for ListenerIo<S>
+where
+    S: futures_lite::AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        mut buf: hyper::rt::ReadBufCursor<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let mut chunk = [0u8; 8 * 1024];
+        let len = buf.remaining().min(chunk.len());
+        match Pin::new(&mut self.0).poll_read(cx, &mut chunk[..len]) {
+            Poll::Ready(Ok(read)) => {
+                buf.put_slice(&chunk[..read]);
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S> hyper::rt::Write for ListenerIo<S>
+where
+    S: futures_lite::AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
+
+/// Adapts an upgraded hyper connection back to futures' io traits.
+struct UpgradedIo(hyper::upgrade::Upgraded);
+
+impl futures_lite::AsyncRead for UpgradedIo {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let mut read_buf = hyper::rt::ReadBuf::new(buf);
+        match hyper::rt::Read::poll_read(Pin::new(&mut self.0), cx, read_buf.unfilled()) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(read_buf.filled().len())),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl futures_lite::AsyncWrite for UpgradedIo {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        hyper::rt::Write::poll_write(Pin::new(&mut self.0), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        hyper::rt::Write::poll_flush(Pin::new(&mut self.0), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        hyper::rt::Write::poll_shutdown(Pin::new(&mut self.0), cx)
+    }
+}
+
+/// Drives hyper's header read timeout with async-io timers.
+#[derive(Debug, Clone)]
+struct ListenerTimer;
+
+impl hyper::rt::Timer for ListenerTimer {
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn hyper::rt::Sleep>> {
+        Box::pin(ListenerSleep(async_io::Timer::after(duration)))
+    }
+
+    fn sleep_until(&self, deadline: std::time::Instant) -> Pin<Box<dyn hyper::rt::Sleep>> {
+        Box::pin(ListenerSleep(async_io::Timer::at(deadline)))
+    }
+}
+
+struct ListenerSleep(async_io::Timer);
+
+impl Future for ListenerSleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        Pin::new(&mut self.0).poll(cx).map(|_| ())
+    }
+}
+
+impl hyper::rt::Sleep for ListenerSleep {}
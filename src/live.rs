@@ -0,0 +1,327 @@
+//! The visitor count pushed to browsers over WebSocket as it changes.
+//!
+//! `GET /live/count` upgrades to a WebSocket that receives the [`Count`] as
+//! a JSON text message, ie `{"count":42,"name":"pete"}`, once on connecting
+//! and then whenever a greeting increments it. The `name` is only present
+//! if the visitor gave one. `public/live/index.html` shows it at `/live`.
+//!
+//! The handshake is answered by [`live_count`] like any other route, after
+//! which the [`HttpListener`] hands the upgraded connection to the
+//! [`LiveCount`]. Each connection is pinged every
+//! [`LiveCount::heartbeat`] and dropped if the client stops answering, at
+//! most [`LiveCount::max_clients`] are open at once, and all of them are
+//! closed with `1001 Going Away` when the server shuts down.
+use crate::prelude::*;
+use async_tungstenite::WebSocketStream;
+use async_tungstenite::tungstenite::Message;
+use async_tungstenite::tungstenite::protocol::CloseFrame;
+use async_tungstenite::tungstenite::protocol::Role;
+use async_tungstenite::tungstenite::protocol::WebSocketConfig;
+use async_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use beet::exports::async_channel;
+use beet::exports::futures_lite;
+use beet::exports::futures_lite::StreamExt;
+use beet::exports::http;
+use beet::prelude::*;
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+/// The route that upgrades to the live count WebSocket.
+pub const LIVE_COUNT_ROUTE: &str = "/live/count";
+
+/// Appended to the client's key to derive `Sec-WebSocket-Accept`, from RFC 6455.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Updates queued for a client before it is considered too slow and
+/// skips them, only the latest count matters.
+const CLIENT_QUEUE: usize = 16;
+
+/// How long to wait for the client to acknowledge a close.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A change of the visitor count, as sent to clients.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CountUpdate {
+    /// The new visitor count.
+    pub count: u32,
+    /// The visitor's name, if they gave one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// The clients of the live count WebSocket, shared by every clone.
+#[derive(Debug, Clone, Resource)]
+pub struct LiveCount {
+    max_clients: usize,
+    heartbeat: Duration,
+    clients: Arc<Mutex<Vec<async_channel::Sender<CountUpdate>>>>,
+    active: Arc<AtomicUsize>,
+    count: Arc<AtomicU32>,
+}
+
+impl Default for LiveCount {
+    fn default() -> Self {
+        Self {
+            max_clients: 100,
+            heartbeat: Duration::from_secs(30),
+            clients: default(),
+            active: default(),
+            count: default(),
+        }
+    }
+}
+
+/// A slot for a client, released on drop.
+struct LiveClient {
+    updates: async_channel::Receiver<CountUpdate>,
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for LiveClient {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl LiveCount {
+    /// Allow at most this many clients at once, defaults to `100`.
+    pub fn with_max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = max_clients;
+        self
+    }
+
+    /// Ping clients this often, dropping those that send nothing for two
+    /// intervals, defaults to 30 seconds.
+    pub fn with_heartbeat(mut self, heartbeat: Duration) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    /// The maximum number of clients.
+    pub fn max_clients(&self) -> usize {
+        self.max_clients
+    }
+
+    /// How often clients are pinged.
+    pub fn heartbeat(&self) -> Duration {
+        self.heartbeat
+    }
+
+    /// The number of clients connected.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Whether no more clients can connect.
+    pub fn is_full(&self) -> bool {
+        self.active() >= self.max_clients
+    }
+
+    /// The latest count, sent to clients when they connect.
+    pub fn count(&self) -> u32 {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Send the update to every client.
+    pub fn publish(&self, update: CountUpdate) {
+        self.count.store(update.count, Ordering::SeqCst);
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|client| match client.try_send(update.clone()) {
+            Ok(()) | Err(async_channel::TrySendError::Full(_)) => true,
+            Err(async_channel::TrySendError::Closed(_)) => false,
+        });
+    }
+
+    /// Take a slot for a client, `None` if there are already
+    /// [`LiveCount::max_clients`].
+    fn join(&self) -> Option<LiveClient> {
+        self.active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                (active < self.max_clients).then_some(active + 1)
+            })
+            .ok()?;
+        let (sender, updates) = async_channel::bounded(CLIENT_QUEUE);
+        self.clients.lock().unwrap().push(sender);
+        Some(LiveClient {
+            updates,
+            active: self.active.clone(),
+        })
+    }
+
+    /// Serve a connection the [`live_count`] handshake was accepted on,
+    /// starting with the latest count, until either side closes it or the
+    /// [`Connections`] stop.
+    pub async fn serve<S>(&self, stream: S, connections: &Connections)
+    where
+        S: futures_lite::AsyncRead + futures_lite::AsyncWrite + Unpin,
+    {
+        // clients only send control frames
+        let config = WebSocketConfig::default()
+            .max_message_size(Some(1024))
+            .max_frame_size(Some(1024));
+        let mut socket = WebSocketStream::from_raw_socket(stream, Role::Server, Some(config)).await;
+        // the handshake checked too, but another client may have joined since
+        let Some(client) = self.join() else {
+            close(&mut socket, CloseCode::Again, "too many clients").await;
+            return;
+        };
+        let initial = CountUpdate {
+            count: self.count(),
+            name: None,
+        };
+        if socket.send(update_message(&initial)).await.is_err() {
+            return;
+        }
+
+        let mut heartbeat = async_io::Timer::interval(self.heartbeat);
+        let mut last_seen = Instant::now();
+        loop {
+            let event = futures_lite::future::or(
+                futures_lite::future::or(async { LiveEvent::Frame(socket.next().await) }, async {
+                    LiveEvent::Update(client.updates.recv().await.ok())
+                }),
+                futures_lite::future::or(
+                    async {
+                        heartbeat.next().await;
+                        LiveEvent::Heartbeat
+                    },
+                    async {
+                        connections.stopped().await;
+                        LiveEvent::Stop
+                    },
+                ),
+            )
+            .await;
+            let sent = match event {
+                // pongs to the client's pings are sent by the socket
+                LiveEvent::Frame(Some(Ok(_))) => {
+                    last_seen = Instant::now();
+                    Ok(())
+                }
+                LiveEvent::Frame(Some(Err(err))) => {
+                    trace!("Live count client errored: {}", err);
+                    break;
+                }
+                LiveEvent::Frame(None) => break,
+                LiveEvent::Update(Some(update)) => socket.send(update_message(&update)).await,
+                LiveEvent::Update(None) => break,
+                LiveEvent::Heartbeat if last_seen.elapsed() > self.heartbeat * 2 => {
+                    trace!("Live count client stopped answering pings");
+                    break;
+                }
+                LiveEvent::Heartbeat => socket.send(Message::Ping(default())).await,
+                LiveEvent::Stop => {
+                    close(&mut socket, CloseCode::Away, "server shutting down").await;
+                    break;
+                }
+            };
+            if sent.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// What woke a live count connection.
+enum LiveEvent {
+    Frame(Option<Result<Message, async_tungstenite::tungstenite::Error>>),
+    Update(Option<CountUpdate>),
+    Heartbeat,
+    Stop,
+}
+
+fn update_message(update: &CountUpdate) -> Message {
+    Message::text(serde_json::to_string(update).unwrap())
+}
+
+/// Send a close frame and wait briefly for the client to answer it.
+async fn close<S>(socket: &mut WebSocketStream<S>, code: CloseCode, reason: &str)
+where
+    S: futures_lite::AsyncRead + futures_lite::AsyncWrite + Unpin,
+{
+    let frame = CloseFrame {
+        code,
+        reason: reason.into(),
+    };
+    if socket.close(Some(frame)).await.is_err() {
+        return;
+    }
+    futures_lite::future::or(async { while socket.next().await.is_some() {} }, async {
+        async_io::Timer::after(CLOSE_TIMEOUT).await;
+    })
+    .await;
+}
+
+/// `GET /live/count`, accept the WebSocket handshake for the [`LiveCount`].
+///
+/// Plain requests get `426 Upgrade Required`, and a handshake while the
+/// live count is full gets `503 Service Unavailable`.
+pub fn live_count(server: &mut EntityWorldMut, request: Request) -> Response {
+    let header_has = |header: &str, token: &str| {
+        request.get_header(header).is_some_and(|value| {
+            value
+                .split(',')
+                .any(|value| value.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    if !header_has("upgrade", "websocket") || !header_has("connection", "upgrade") {
+        return Response::from_status_body(
+            http::StatusCode::UPGRADE_REQUIRED.into(),
+            "Upgrade Required: connect with a WebSocket, or visit /live",
+            "text/plain",
+        )
+        .with_header("upgrade", "websocket")
+        .with_header("connection", "upgrade");
+    }
+    if request.get_header("sec-websocket-version") != Some("13") {
+        return Response::from_status_body(
+            http::StatusCode::UPGRADE_REQUIRED.into(),
+            "Upgrade Required: only WebSocket version 13 is supported",
+            "text/plain",
+        )
+        .with_header("sec-websocket-version", "13");
+    }
+    let Some(key) = request
+        .get_header("sec-websocket-key")
+        .map(str::trim)
+        .filter(|key| BASE64.decode(key).is_ok_and(|key| key.len() == 16))
+    else {
+        return Response::from_status_body(
+            http::StatusCode::BAD_REQUEST.into(),
+            "Bad Request: invalid Sec-WebSocket-Key",
+            "text/plain",
+        );
+    };
+    let live = server.resource::<LiveCount>();
+    if live.is_full() {
+        return Response::from_status_body(
+            http::StatusCode::SERVICE_UNAVAILABLE.into(),
+            "Service Unavailable: too many live count clients",
+            "text/plain",
+        )
+        .with_header("retry-after", "30");
+    }
+    // the count may have been loaded or set without a greeting
+    if let Some(count) = server.get::<Count>() {
+        live.count.store(count.0, Ordering::SeqCst);
+    }
+    Response::from_status(http::StatusCode::SWITCHING_PROTOCOLS.into())
+        .with_header("upgrade", "websocket")
+        .with_header("connection", "upgrade")
+        .with_header("sec-websocket-accept", &websocket_accept(key))
+}
+
+/// The `Sec-WebSocket-Accept` for a `Sec-WebSocket-Key`.
+pub fn websocket_accept(key: &str) -> String {
+    let digest = ring::digest::digest(
+        &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+        format!("{key}{WEBSOCKET_GUID}").as_bytes(),
+    );
+    BASE64.encode(digest)
+}
@@ -0,0 +1,208 @@
+//! Greetings in the visitor's language, from Fluent message catalogs.
+//!
+//! Each locale has a catalog at `locales/{locale}/*.ftl`, which pluralizes
+//! and picks ordinal suffixes with the locale's rules, ie `you are the 2nd
+//! visitor`. The crate's catalogs are compiled in, or read once at startup
+//! from `HELLO_LOCALES_DIR` so translations can be deployed without a
+//! rebuild.
+//!
+//! The locale is picked by [`Catalogs::negotiate`] from the `?lang=`
+//! parameter or the `Accept-Language` header, and messages missing from a
+//! catalog fall back to English.
+use crate::prelude::*;
+use beet::prelude::*;
+use fluent_bundle::FluentArgs;
+use fluent_bundle::FluentResource;
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_langneg::NegotiationStrategy;
+use fluent_langneg::negotiate_languages;
+use std::path::Path;
+use std::sync::Arc;
+use unic_langid::LanguageIdentifier;
+
+/// The locale every other falls back to, which must have a catalog.
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// The crate's `locales/` catalogs, compiled in.
+const EMBEDDED_CATALOGS: [(&str, &str); 4] = [
+    ("en", include_str!("../locales/en/greeting.ftl")),
+    ("de", include_str!("../locales/de/greeting.ftl")),
+    ("fr", include_str!("../locales/fr/greeting.ftl")),
+    ("es", include_str!("../locales/es/greeting.ftl")),
+];
+
+/// The message catalogs by locale, cheap to clone.
+#[derive(Clone, Resource)]
+pub struct Catalogs {
+    /// The [`DEFAULT_LOCALE`] first.
+    bundles: Arc<Vec<FluentBundle<FluentResource>>>,
+    /// The locale of each bundle, for negotiation.
+    locales: Vec<LanguageIdentifier>,
+}
+
+impl std::fmt::Debug for Catalogs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Catalogs")
+            .field("locales", &self.locales)
+            .finish()
+    }
+}
+
+impl Default for Catalogs {
+    fn default() -> Self {
+        Self::embedded()
+    }
+}
+
+impl Catalogs {
+    /// The catalogs compiled into the binary.
+    pub fn embedded() -> Self {
+        Self::from_sources(
+            EMBEDDED_CATALOGS
+                .iter()
+                .map(|(locale, source)| (locale.to_string(), vec![source.to_string()])),
+        )
+        .expect("the embedded catalogs are valid")
+    }
+
+    /// Read the catalogs in a directory, one subdirectory of `.ftl` files
+    /// per locale, ie `locales/de/greeting.ftl`.
+    ///
+    /// Fails if a file can't be read or parsed, or there is no catalog
+    /// for the [`DEFAULT_LOCALE`].
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let read_dir = |dir: &Path| {
+            let mut paths = std::fs::read_dir(dir)
+                .and_then(|entries| {
+                    entries
+                        .map(|entry| entry.map(|entry| entry.path()))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .map_err(|err| format!("{}: {}", dir.display(), err))?;
+            // load in a stable order, so the same files give the same catalogs
+            paths.sort();
+            Ok::<_, String>(paths)
+        };
+        let mut catalogs = Vec::new();
+        for locale_dir in read_dir(dir)?.into_iter().filter(|path| path.is_dir()) {
+            let locale = locale_dir
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            let mut sources = Vec::new();
+            for path in read_dir(&locale_dir)? {
+                if path.extension().is_some_and(|extension| extension == "ftl") {
+                    let source = std::fs::read_to_string(&path)
+                        .map_err(|err| format!("{}: {}", path.display(), err))?;
+                    sources.push(source);
+                }
+            }
+            catalogs.push((locale, sources));
+        }
+        Self::from_sources(catalogs)
+    }
+
+    /// Parse the sources of each locale's catalog.
+    fn from_sources(
+        catalogs: impl IntoIterator<Item = (String, Vec<String>)>,
+    ) -> Result<Self, String> {
+        let mut bundles = Vec::new();
+        for (locale, sources) in catalogs {
+            let langid: LanguageIdentifier = locale
+                .parse()
+                .map_err(|err| format!("{locale} is not a locale: {err}"))?;
+            let mut bundle = FluentBundle::new_concurrent(vec![langid]);
+            // the isolation marks around arguments would end up in plain text
+            bundle.set_use_isolating(false);
+            bundle
+                .add_builtins()
+                .map_err(|err| format!("{locale}: {err}"))?;
+            for source in sources {
+                let resource = FluentResource::try_new(source).map_err(|(_, errors)| {
+                    format!(
+                        "{locale}: {}",
+                        errors.first().map(ToString::to_string).unwrap_or_default()
+                    )
+                })?;
+                bundle.add_resource(resource).map_err(|errors| {
+                    format!(
+                        "{locale}: {}",
+                        errors.first().map(ToString::to_string).unwrap_or_default()
+                    )
+                })?;
+            }
+            bundles.push(bundle);
+        }
+        let default = bundles
+            .iter()
+            .position(|bundle| bundle.locales[0] == DEFAULT_LOCALE)
+            .ok_or_else(|| format!("there is no catalog for {DEFAULT_LOCALE}"))?;
+        bundles.swap(0, default);
+        let locales = bundles
+            .iter()
+            .map(|bundle| bundle.locales[0].clone())
+            .collect();
+        Ok(Self {
+            bundles: Arc::new(bundles),
+            locales,
+        })
+    }
+
+    /// The locales with a catalog, the [`DEFAULT_LOCALE`] first.
+    pub fn locales(&self) -> &[LanguageIdentifier] {
+        &self.locales
+    }
+
+    /// The best locale for the request: the one named by `?lang=`, or else
+    /// the most preferred in `Accept-Language` with a catalog, falling back
+    /// to the [`DEFAULT_LOCALE`]. `de-AT` is served by `de`.
+    pub fn negotiate(&self, request: &Request) -> &LanguageIdentifier {
+        let requested: Vec<LanguageIdentifier> = match request.get_param("lang") {
+            Some(lang) => lang.parse().into_iter().collect(),
+            None => {
+                let mut ranges = request
+                    .get_header("accept-language")
+                    .map(parse_accept)
+                    .unwrap_or_default();
+                // stable, so equally preferred locales keep their order
+                ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+                ranges
+                    .into_iter()
+                    .filter(|(_, quality)| *quality > 0.)
+                    .filter_map(|(range, _)| range.parse().ok())
+                    .collect()
+            }
+        };
+        negotiate_languages(
+            &requested,
+            &self.locales,
+            Some(&self.locales[0]),
+            NegotiationStrategy::Lookup,
+        )[0]
+    }
+
+    /// Format a message in the locale, falling back to the
+    /// [`DEFAULT_LOCALE`], or the message id if neither has it.
+    pub fn format(&self, locale: &LanguageIdentifier, id: &str, args: &FluentArgs) -> String {
+        let position = self.locales.iter().position(|other| other == locale);
+        let found = position
+            .into_iter()
+            .chain([0])
+            .map(|index| &self.bundles[index])
+            .find_map(|bundle| {
+                let pattern = bundle.get_message(id)?.value()?;
+                Some((bundle, pattern))
+            });
+        let Some((bundle, pattern)) = found else {
+            warn!("No message {} for {}", id, locale);
+            return id.to_string();
+        };
+        let mut errors = Vec::new();
+        let message = bundle.format_pattern(pattern, Some(args), &mut errors);
+        if let Some(err) = errors.first() {
+            warn!("Failed to format {} for {}: {}", id, locale, err);
+        }
+        message.into_owned()
+    }
+}
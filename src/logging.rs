@@ -0,0 +1,58 @@
+//! A log level that can be changed while the server is running.
+//!
+//! Bevy's [`LogPlugin`] builds its filter once at startup, so
+//! [`AppConfig::log_plugin`](crate::prelude::AppConfig::log_plugin) lets
+//! every level through it and the formatted output is filtered by
+//! [`log_level`] instead, which the admin API adjusts with
+//! [`set_log_level`]. When `RUST_LOG` is set it still filters first.
+use beet::exports::bevy::log::BoxedFmtLayer;
+use beet::exports::bevy::log::tracing::Metadata;
+use beet::exports::bevy::log::tracing_subscriber;
+use beet::prelude::*;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering;
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::layer::Filter;
+
+/// From least to most verbose, indexed by [`LEVEL`].
+const LEVELS: [Level; 5] = [
+    Level::ERROR,
+    Level::WARN,
+    Level::INFO,
+    Level::DEBUG,
+    Level::TRACE,
+];
+
+/// The subscriber is global, so is its level.
+static LEVEL: AtomicU8 = AtomicU8::new(2);
+
+/// The most verbose level currently written.
+pub fn log_level() -> Level {
+    LEVELS[LEVEL.load(Ordering::Relaxed) as usize]
+}
+
+/// Write events up to this level from now on.
+pub fn set_log_level(level: Level) {
+    let index = LEVELS.iter().position(|l| *l == level).unwrap_or(2);
+    LEVEL.store(index as u8, Ordering::Relaxed);
+}
+
+/// A [`LogPlugin::fmt_layer`] writing to stderr, filtered by [`log_level`].
+pub fn runtime_level_fmt_layer(_app: &mut App) -> Option<BoxedFmtLayer> {
+    Some(Box::new(
+        tracing_subscriber::fmt::Layer::default()
+            .with_writer(std::io::stderr)
+            .with_filter(RuntimeLevel),
+    ))
+}
+
+struct RuntimeLevel;
+
+// `callsite_enabled` defaults to `Interest::sometimes`, so events are
+// checked every time rather than cached per callsite
+impl<S> Filter<S> for RuntimeLevel {
+    fn enabled(&self, meta: &Metadata<'_>, _cx: &Context<'_, S>) -> bool {
+        *meta.level() <= log_level()
+    }
+}
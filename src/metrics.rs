@@ -0,0 +1,215 @@
+//! Prometheus metrics for the server.
+//!
+//! Every request dispatched by the [`HelloLightsailPlugin`] is recorded in
+//! the [`Metrics`] resource, which the `/metrics` route renders in the
+//! Prometheus text exposition format alongside process stats read from
+//! `/proc/self`.
+use crate::prelude::*;
+use beet::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Upper bounds in seconds of the request latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+
+/// Linux reports cpu times in clock ticks of `USER_HZ`, which is 100
+/// on every architecture we deploy to.
+const CLOCK_TICKS_PER_SECOND: f64 = 100.0;
+
+/// Request counters and latency histograms collected by the server.
+#[derive(Debug, Clone, Resource)]
+pub struct Metrics {
+    started: Instant,
+    /// Keyed by `(method, route, status)`.
+    requests: BTreeMap<(String, String, u16), u64>,
+    /// Keyed by `(method, route)`.
+    latency: BTreeMap<(String, String), Histogram>,
+    /// Requests rejected by the [`RateLimiter`], keyed by route.
+    throttled: BTreeMap<String, u64>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            requests: default(),
+            latency: default(),
+            throttled: default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    /// Non-cumulative counts per bucket, plus one for `+Inf`.
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        let index = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[index] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+impl Metrics {
+    /// Record a completed request.
+    ///
+    /// The `route` should be the matched route pattern rather than the
+    /// raw request path, so that arbitrary 404 paths cannot blow up the
+    /// number of series.
+    pub fn observe(&mut self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        *self
+            .requests
+            .entry((method.to_string(), route.to_string(), status))
+            .or_default() += 1;
+        self.latency
+            .entry((method.to_string(), route.to_string()))
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Record a request rejected by the [`RateLimiter`], in addition to
+    /// its `429` passed to [`Metrics::observe`].
+    pub fn throttle(&mut self, route: &str) {
+        *self.throttled.entry(route.to_string()).or_default() += 1;
+    }
+
+    /// Time since the metrics were initialized, ie the process uptime.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self, visitor_count: u32) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Total HTTP requests handled.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, route, status), count) in &self.requests {
+            writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",path=\"{}\",status=\"{}\"}} {}",
+                method,
+                escape_label(route),
+                status,
+                count
+            )
+            .unwrap();
+        }
+
+        out.push_str("# HELP http_request_duration_seconds HTTP request latency.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((method, route), histogram) in &self.latency {
+            let labels = format!("method=\"{}\",path=\"{}\"", method, escape_label(route));
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{labels}}} {}",
+                histogram.sum
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "http_request_duration_seconds_count{{{labels}}} {}",
+                histogram.count
+            )
+            .unwrap();
+        }
+
+        out.push_str(
+            "# HELP http_requests_throttled_total Requests rejected by the rate limiter.\n",
+        );
+        out.push_str("# TYPE http_requests_throttled_total counter\n");
+        for (route, count) in &self.throttled {
+            writeln!(
+                out,
+                "http_requests_throttled_total{{path=\"{}\"}} {}",
+                escape_label(route),
+                count
+            )
+            .unwrap();
+        }
+
+        out.push_str("# HELP hello_visitor_count Number of visitors greeted.\n");
+        out.push_str("# TYPE hello_visitor_count gauge\n");
+        writeln!(out, "hello_visitor_count {visitor_count}").unwrap();
+
+        out.push_str("# HELP process_uptime_seconds Time since the server started.\n");
+        out.push_str("# TYPE process_uptime_seconds gauge\n");
+        writeln!(
+            out,
+            "process_uptime_seconds {}",
+            self.uptime().as_secs_f64()
+        )
+        .unwrap();
+
+        if let Some(bytes) = resident_memory_bytes() {
+            out.push_str("# HELP process_resident_memory_bytes Resident memory size in bytes.\n");
+            out.push_str("# TYPE process_resident_memory_bytes gauge\n");
+            writeln!(out, "process_resident_memory_bytes {bytes}").unwrap();
+        }
+        if let Some(seconds) = cpu_seconds() {
+            out.push_str("# HELP process_cpu_seconds_total Total user and system CPU time spent in seconds.\n");
+            out.push_str("# TYPE process_cpu_seconds_total counter\n");
+            writeln!(out, "process_cpu_seconds_total {seconds}").unwrap();
+        }
+        out
+    }
+}
+
+/// `GET /metrics`, all [`Metrics`] in the Prometheus text format.
+pub fn metrics(server: &mut EntityWorldMut, _request: Request) -> Response {
+    let visitor_count = server.get::<Count>().map(|count| count.0).unwrap_or(0);
+    let body = server.resource::<Metrics>().render(visitor_count);
+    Response::ok_body(body, "text/plain; version=0.0.4; charset=utf-8")
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Read `VmRSS` from `/proc/self/status`, which is reported in kB.
+fn resident_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// Sum `utime` and `stime` from `/proc/self/stat`.
+fn cpu_seconds() -> Option<f64> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // the command name may contain spaces, so start after its closing paren,
+    // at which point `state` is the first field
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some((utime + stime) as f64 / CLOCK_TICKS_PER_SECOND)
+}
@@ -0,0 +1,271 @@
+//! Visitor names, validated and counted.
+//!
+//! A `name` parameter is only greeted once it parses as a [`VisitorName`],
+//! so what reaches a response is short, normalized and free of control
+//! characters and markup. Names are counted under [`VisitorName::key`], so
+//! that `Pete`, `PETE` and `pete` are the same visitor. Only the most
+//! recently seen names are kept, so a client cycling through random names
+//! can't grow the table without limit.
+use crate::prelude::*;
+use beet::prelude::*;
+use lru::LruCache;
+use percent_encoding::percent_decode_str;
+use serde_json::json;
+use std::num::NonZeroUsize;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+/// Names longer than this many characters are rejected.
+pub const MAX_NAME_CHARS: usize = 32;
+
+/// Punctuation allowed in a name besides spaces, ie `O'Brien` or `St. John`.
+const NAME_PUNCTUATION: [char; 4] = ['\'', '\u{2019}', '-', '.'];
+
+/// What a name may contain, for [`NameError`] messages.
+const NAME_CHARS_ALLOWED: &str = "only letters, numbers, spaces and ' - . are allowed";
+
+/// Decode a percent-encoded query parameter value, where `+` is a space.
+///
+/// Route captures are inserted with any `+` encoded, see [`Router`], so a
+/// `+` in a path stays a `+`.
+pub fn decode_param(value: &str) -> String {
+    percent_decode_str(&value.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// A visitor name that passed [`VisitorName::parse`].
+///
+/// It is NFC normalized, between 1 and [`MAX_NAME_CHARS`] characters, has
+/// no leading, trailing or repeated whitespace, and contains only letters,
+/// numbers, combining marks following them, spaces and `' ’ - .`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VisitorName(String);
+
+/// Why a [`VisitorName`] was rejected, its message safe to show to the visitor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameError {
+    /// Empty or only whitespace.
+    Blank,
+    /// More than [`MAX_NAME_CHARS`] characters.
+    TooLong,
+    /// A character outside the allowed classes, ie `<`, a control character
+    /// or a combining mark with nothing to combine with.
+    InvalidChar(char),
+}
+
+impl std::fmt::Display for NameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Blank => write!(f, "the name is blank"),
+            Self::TooLong => write!(f, "the name is longer than {MAX_NAME_CHARS} characters"),
+            // only echo characters that can't hide or reorder text
+            Self::InvalidChar(char) if char.is_ascii_graphic() => write!(
+                f,
+                "the name contains '{char}' (U+{:04X}), {NAME_CHARS_ALLOWED}",
+                *char as u32
+            ),
+            Self::InvalidChar(char) => write!(
+                f,
+                "the name contains U+{:04X}, {NAME_CHARS_ALLOWED}",
+                *char as u32
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NameError {}
+
+impl VisitorName {
+    /// Validate a decoded name: NFC normalize it, trim it and collapse
+    /// runs of whitespace into a single space, then check its length and
+    /// characters.
+    pub fn parse(name: &str) -> Result<Self, NameError> {
+        let mut parsed = String::new();
+        let mut chars = 0;
+        let mut space = false;
+        for char in name.nfc() {
+            if char.is_control() {
+                return Err(NameError::InvalidChar(char));
+            } else if char.is_whitespace() {
+                space = !parsed.is_empty();
+                continue;
+            }
+            let previous = parsed.chars().next_back().filter(|_| !space);
+            let allowed = if is_combining_mark(char) {
+                previous.is_some_and(|base| base.is_alphanumeric() || is_combining_mark(base))
+            } else {
+                char.is_alphanumeric() || NAME_PUNCTUATION.contains(&char)
+            };
+            if !allowed {
+                return Err(NameError::InvalidChar(char));
+            }
+            if std::mem::take(&mut space) {
+                parsed.push(' ');
+                chars += 1;
+            }
+            parsed.push(char);
+            chars += 1;
+            // stop early rather than normalizing a huge parameter
+            if chars > MAX_NAME_CHARS {
+                return Err(NameError::TooLong);
+            }
+        }
+        if parsed.is_empty() {
+            Err(NameError::Blank)
+        } else {
+            Ok(Self(parsed))
+        }
+    }
+
+    /// The normalized name, for display.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The key the name is counted under: case-folded and NFC normalized.
+    ///
+    /// The key isn't truncated, as folding can lengthen it, ie `ß` to `ss`,
+    /// and names that differ past the cut would be counted as one. The name
+    /// is at most [`MAX_NAME_CHARS`], so the key is at most three times that.
+    pub fn key(&self) -> String {
+        // folding can leave the string unnormalized, ie for final sigma
+        caseless::default_case_fold_str(&self.0).nfc().collect()
+    }
+}
+
+impl std::fmt::Display for VisitorName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::str::FromStr for VisitorName {
+    type Err = NameError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::parse(name)
+    }
+}
+
+/// Visit counts keyed by normalized name, evicting the least recently
+/// visited name once full.
+#[derive(Debug, Clone, Component)]
+pub struct NameCounts {
+    counts: LruCache<String, u32>,
+}
+
+impl Default for NameCounts {
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
+
+impl NameCounts {
+    /// Track at most `capacity` names, at least one.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            counts: LruCache::new(NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN)),
+        }
+    }
+
+    /// Record a visit by the normalized name, returning its visit count.
+    pub fn visit(&mut self, name: &str) -> u32 {
+        let count = self.counts.get_or_insert_mut(name.to_string(), || 0);
+        *count = count.saturating_add(1);
+        *count
+    }
+
+    /// The visit count for a normalized name without marking it as recently used.
+    pub fn get(&self, name: &str) -> Option<u32> {
+        self.counts.peek(name).copied()
+    }
+
+    /// The number of names tracked.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Whether no names are tracked.
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// The maximum number of names tracked.
+    pub fn capacity(&self) -> usize {
+        self.counts.cap().get()
+    }
+
+    /// The `limit` most frequent visitors, ties broken by name.
+    pub fn top(&self, limit: usize) -> Vec<(String, u32)> {
+        let mut top: Vec<(String, u32)> = self
+            .counts
+            .iter()
+            .map(|(name, count)| (name.clone(), *count))
+            .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(limit);
+        top
+    }
+
+    /// All counts from least to most recently visited, for persisting.
+    pub fn to_vec(&self) -> Vec<(String, u32)> {
+        self.counts
+            .iter()
+            .rev()
+            .map(|(name, count)| (name.clone(), *count))
+            .collect()
+    }
+
+    /// Replace the counts with ones from [`NameCounts::to_vec`],
+    /// keeping the most recent if there are more than the capacity.
+    pub fn restore(&mut self, counts: impl IntoIterator<Item = (String, u32)>) {
+        self.counts.clear();
+        for (name, count) in counts {
+            self.counts.put(name, count);
+        }
+    }
+}
+
+/// Representations of the leaderboard, JSON first for scripts.
+const LEADERBOARD_TYPES: [MediaType; 2] = [MediaType::Json, MediaType::Text];
+
+/// Leaders returned when no `?limit=` is given.
+const DEFAULT_LEADERBOARD_LIMIT: usize = 10;
+
+/// Upper bound for `?limit=`.
+const MAX_LEADERBOARD_LIMIT: usize = 100;
+
+/// `GET /leaderboard`, the names with the most visits as JSON or text.
+///
+/// `?limit=` sets how many, defaulting to 10 and capped at 100.
+pub fn leaderboard(server: &mut EntityWorldMut, request: Request) -> Response {
+    let Some(media_type) = negotiate(&request, &LEADERBOARD_TYPES) else {
+        return not_acceptable(&LEADERBOARD_TYPES).with_header("vary", "accept");
+    };
+    let limit = request
+        .get_param("limit")
+        .and_then(|limit| limit.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_LEADERBOARD_LIMIT)
+        .min(MAX_LEADERBOARD_LIMIT);
+    let top = server
+        .get::<NameCounts>()
+        .map(|counts| counts.top(limit))
+        .unwrap_or_default();
+
+    let body = match media_type {
+        MediaType::Text => top
+            .iter()
+            .enumerate()
+            .map(|(index, (name, visits))| format!("{}. {} ({} visits)\n", index + 1, name, visits))
+            .collect::<String>(),
+        _ => json!({
+            "leaders": top
+                .iter()
+                .map(|(name, visits)| json!({ "name": name, "visits": visits }))
+                .collect::<Vec<_>>(),
+        })
+        .to_string(),
+    };
+    Response::ok_body(body, media_type.content_type()).with_header("vary", "accept")
+}
@@ -0,0 +1,158 @@
+//! Content negotiation with the `Accept` header.
+//!
+//! A `?format=json`, `?format=html` or `?format=text` parameter overrides
+//! the header, which is handy from a browser address bar.
+use beet::exports::http;
+use beet::prelude::*;
+
+/// A representation a handler can respond with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaType {
+    /// `text/plain`
+    Text,
+    /// `application/json`
+    Json,
+    /// `text/html`
+    Html,
+}
+
+impl MediaType {
+    /// The `Content-Type` header value, including the charset.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Text => "text/plain; charset=utf-8",
+            Self::Json => "application/json",
+            Self::Html => "text/html; charset=utf-8",
+        }
+    }
+
+    /// The type and subtype, ie `text/plain`.
+    pub fn essence(&self) -> &'static str {
+        match self {
+            Self::Text => "text/plain",
+            Self::Json => "application/json",
+            Self::Html => "text/html",
+        }
+    }
+
+    /// Parse the value of the `?format=` parameter.
+    pub fn from_format(format: &str) -> Option<Self> {
+        match format.trim().to_ascii_lowercase().as_str() {
+            "text" | "txt" | "plain" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            "html" => Some(Self::Html),
+            _ => None,
+        }
+    }
+
+    /// How specifically the media range matches, `None` if it doesn't.
+    /// `*/*` is 0, `text/*` is 1 and `text/plain` is 2.
+    fn match_specificity(&self, range: &str) -> Option<u8> {
+        let (kind, subtype) = self.essence().split_once('/')?;
+        let (range_kind, range_subtype) = range.split_once('/')?;
+        match (range_kind, range_subtype) {
+            ("*", "*") => Some(0),
+            (range_kind, "*") if range_kind.eq_ignore_ascii_case(kind) => Some(1),
+            (range_kind, range_subtype)
+                if range_kind.eq_ignore_ascii_case(kind)
+                    && range_subtype.eq_ignore_ascii_case(subtype) =>
+            {
+                Some(2)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Pick the best of the `supported` types for the request, in order of
+/// the server's preference when the client has none.
+///
+/// Returns `None` if none are acceptable, or the `?format=` parameter
+/// names an unsupported type, which should be answered with
+/// [`not_acceptable`].
+pub fn negotiate(request: &Request, supported: &[MediaType]) -> Option<MediaType> {
+    if let Some(format) = request.get_param("format") {
+        return MediaType::from_format(format).filter(|media_type| supported.contains(media_type));
+    }
+    let Some(accept) = request.get_header("accept") else {
+        return supported.first().copied();
+    };
+    let ranges = parse_accept(accept);
+
+    let mut best: Option<(MediaType, f32)> = None;
+    for media_type in supported {
+        // the most specific matching range decides the quality
+        let quality = ranges
+            .iter()
+            .filter_map(|(range, quality)| Some((media_type.match_specificity(range)?, *quality)))
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, quality)| quality)
+            .unwrap_or(0.);
+        if quality > 0. && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((*media_type, quality));
+        }
+    }
+    best.map(|(media_type, _)| media_type)
+}
+
+/// A `406` listing the supported types.
+pub fn not_acceptable(supported: &[MediaType]) -> Response {
+    let supported = supported
+        .iter()
+        .map(MediaType::essence)
+        .collect::<Vec<_>>()
+        .join(", ");
+    Response::from_status_body(
+        http::StatusCode::NOT_ACCEPTABLE.into(),
+        format!("Not Acceptable, supported types are: {supported}"),
+        "text/plain",
+    )
+}
+
+/// A JSON body with the status, for the probes and admin routes.
+pub fn json_response(status: StatusCode, body: serde_json::Value) -> Response {
+    Response::from_status_body(status, body.to_string(), MediaType::Json.content_type())
+}
+
+/// Add a request header to the response's `Vary`, keeping those already
+/// listed, for responses that depend on it.
+pub fn with_vary(mut response: Response, header: &str) -> Response {
+    let headers = response.response_parts_mut().parts_mut().headers_mut();
+    let mut vary: Vec<String> = headers
+        .remove("vary")
+        .unwrap_or_default()
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect();
+    if !vary
+        .iter()
+        .any(|value| value == "*" || value.eq_ignore_ascii_case(header))
+    {
+        vary.push(header.to_string());
+    }
+    headers.insert("vary".to_string(), vary.join(", "));
+    response
+}
+
+/// Split an `Accept`, `Accept-Encoding` or `Accept-Language` header into
+/// media ranges, codings or language ranges and their `q` values.
+pub(crate) fn parse_accept(accept: &str) -> Vec<(String, f32)> {
+    accept
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let range = params.next()?.trim();
+            if range.is_empty() {
+                return None;
+            }
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.)
+                .clamp(0., 1.);
+            Some((range.to_string(), quality))
+        })
+        .collect()
+}
@@ -0,0 +1,180 @@
+//! The systemd `sd_notify` protocol.
+//!
+//! With `Type=notify` systemd passes a datagram socket in `NOTIFY_SOCKET`
+//! and only considers the service started once it receives `READY=1`,
+//! which is sent after the [`HttpListener`] has bound its port.
+//! With `WatchdogSec` set it also passes `WATCHDOG_USEC`, and restarts the
+//! service if `WATCHDOG=1` is not sent within that interval. The pings are
+//! sent from a system, so a stalled app loop stops them too.
+//!
+//! Outside of systemd every notification is a no-op.
+use crate::prelude::*;
+use beet::prelude::*;
+use std::os::unix::net::SocketAddr;
+use std::os::unix::net::UnixDatagram;
+
+/// Sends state changes to the systemd service manager.
+#[derive(Debug, Resource)]
+pub struct SystemdNotifier {
+    /// The socket and the address to send to, if running under systemd.
+    target: Option<(UnixDatagram, SocketAddr)>,
+    /// How often systemd expects a watchdog ping.
+    watchdog: Option<Duration>,
+}
+
+/// Defaults to [`SystemdNotifier::from_env`].
+impl Default for SystemdNotifier {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+impl SystemdNotifier {
+    /// A notifier that sends nothing.
+    pub fn disabled() -> Self {
+        Self {
+            target: None,
+            watchdog: None,
+        }
+    }
+
+    /// Read `NOTIFY_SOCKET`, `WATCHDOG_USEC` and `WATCHDOG_PID` as set by
+    /// systemd, logging and ignoring invalid values.
+    pub fn from_env() -> Self {
+        let var = |key| std::env::var(key).ok().filter(|value| !value.is_empty());
+        let mut notifier = match var("NOTIFY_SOCKET") {
+            Some(path) => Self::new(&path).unwrap_or_else(|err| {
+                warn!("Failed to open NOTIFY_SOCKET {}: {}", path, err);
+                Self::disabled()
+            }),
+            None => Self::disabled(),
+        };
+        // the watchdog is meant for another process if the pid doesn't match
+        let for_us = var("WATCHDOG_PID")
+            .map(|pid| pid == std::process::id().to_string())
+            .unwrap_or(true);
+        if let Some(usec) = var("WATCHDOG_USEC")
+            && for_us
+        {
+            match usec.parse::<u64>() {
+                Ok(usec) if usec > 0 => {
+                    notifier = notifier.with_watchdog(Duration::from_micros(usec));
+                }
+                _ => warn!("Ignoring invalid WATCHDOG_USEC: {}", usec),
+            }
+        }
+        notifier
+    }
+
+    /// Send notifications to the given socket path, a leading `@` denotes
+    /// an abstract socket as in `NOTIFY_SOCKET`.
+    pub fn new(path: &str) -> std::io::Result<Self> {
+        let addr = match path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                SocketAddr::from_abstract_name(name)?
+            }
+            _ => SocketAddr::from_pathname(path)?,
+        };
+        Ok(Self {
+            target: Some((UnixDatagram::unbound()?, addr)),
+            watchdog: None,
+        })
+    }
+
+    /// Set the interval systemd expects watchdog pings within,
+    /// pings are sent at half this interval.
+    pub fn with_watchdog(mut self, interval: Duration) -> Self {
+        self.watchdog = Some(interval);
+        self
+    }
+
+    /// Whether there is a service manager to notify.
+    pub fn is_enabled(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /// The watchdog interval, if enabled.
+    pub fn watchdog(&self) -> Option<Duration> {
+        self.watchdog.filter(|_| self.is_enabled())
+    }
+
+    /// Send newline separated `KEY=VALUE` assignments, ie `READY=1`.
+    pub fn notify(&self, state: &str) -> std::io::Result<()> {
+        match &self.target {
+            Some((socket, addr)) => socket.send_to_addr(state.as_bytes(), addr).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    fn notify_or_warn(&self, state: &str) {
+        if let Err(err) = self.notify(state) {
+            warn!("Failed to notify systemd of {:?}: {}", state, err);
+        }
+    }
+}
+
+/// Sends `READY=1` once listening, `STATUS=` with the visitor count,
+/// `WATCHDOG=1` pings and `STOPPING=1` on shutdown.
+///
+/// Insert a [`SystemdNotifier`] resource before adding the plugin to
+/// override the one read from the environment.
+#[derive(Default)]
+pub struct NotifyPlugin;
+
+impl Plugin for NotifyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SystemdNotifier>();
+        let notifier = app.world().resource::<SystemdNotifier>();
+        if !notifier.is_enabled() {
+            return;
+        }
+        if let Some(interval) = notifier.watchdog() {
+            app.insert_resource(WatchdogTimer(Timer::new(
+                interval / 2,
+                TimerMode::Repeating,
+            )))
+            .add_systems(Update, ping_watchdog);
+        }
+        app.add_systems(Update, (notify_ready, notify_status))
+            .add_observer(notify_stopping);
+    }
+}
+
+#[derive(Resource)]
+struct WatchdogTimer(Timer);
+
+fn notify_ready(
+    notifier: Res<SystemdNotifier>,
+    listeners: Query<&Listening, Added<Listening>>,
+    mut ready: Local<bool>,
+) {
+    if *ready {
+        return;
+    }
+    if let Some(Listening(addr)) = listeners.iter().next() {
+        notifier.notify_or_warn(&format!("READY=1\nSTATUS=Listening on {addr}"));
+        *ready = true;
+    }
+}
+
+fn notify_status(notifier: Res<SystemdNotifier>, counts: Query<&Count, Changed<Count>>) {
+    if let Some(count) = counts.iter().next() {
+        notifier.notify_or_warn(&format!("STATUS=Greeted {} visitors", count.0));
+    }
+}
+
+fn ping_watchdog(
+    time: Res<Time>,
+    notifier: Res<SystemdNotifier>,
+    mut timer: ResMut<WatchdogTimer>,
+) {
+    if timer.0.tick(time.delta()).just_finished() {
+        notifier.notify_or_warn("WATCHDOG=1");
+    }
+}
+
+fn notify_stopping(_ev: On<ShutdownStarted>, notifier: Res<SystemdNotifier>) {
+    notifier.notify_or_warn("STOPPING=1\nSTATUS=Draining connections");
+}
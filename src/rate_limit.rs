@@ -0,0 +1,324 @@
+//! Per-client token bucket rate limiting.
+//!
+//! Each client gets a bucket per route pattern holding up to
+//! [`RateLimit::burst`] tokens, refilled at a steady rate. Every request
+//! takes a token, and once the bucket is empty requests are answered with
+//! [`too_many_requests`] instead of reaching the handler, so a `curl` loop
+//! can neither inflate the visitor count nor pin the CPU.
+//!
+//! Clients are identified by IP address, see [`client_ip`] for when the
+//! `X-Forwarded-For` header is trusted.
+use crate::prelude::*;
+use beet::exports::http;
+use beet::prelude::*;
+use lru::LruCache;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+
+/// How many requests a client may make to a route.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    burst: u32,
+    refill_interval: Duration,
+}
+
+impl RateLimit {
+    /// Allow `requests` per `period`, all of which may be made at once.
+    ///
+    /// # Panics
+    ///
+    /// If `requests` is zero.
+    pub fn new(requests: u32, period: Duration) -> Self {
+        assert!(requests > 0, "a rate limit must allow at least one request");
+        Self {
+            burst: requests,
+            refill_interval: period / requests,
+        }
+    }
+
+    /// Allow `requests` per second.
+    pub fn per_second(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(1))
+    }
+
+    /// Allow `requests` per minute.
+    pub fn per_minute(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(60))
+    }
+
+    /// Set how many requests may be made at once, at least one.
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    /// The most requests that may be made at once.
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+
+    /// How long it takes to regain a single request.
+    pub fn refill_interval(&self) -> Duration {
+        self.refill_interval
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    /// Parse `<requests>/<unit>` where the unit is `s`, `m` or `h`, ie
+    /// `30/m`, optionally followed by `+<burst>`, ie `30/m+10` for bursts
+    /// of up to 10. Without a burst all the requests may be made at once.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const EXPECTED: &str = "expected <requests>/<s|m|h>[+<burst>], ie 30/m+10";
+        let (rate, burst) = match s.split_once('+') {
+            Some((rate, burst)) => (rate, Some(burst)),
+            None => (s, None),
+        };
+        let (requests, unit) = rate.split_once('/').ok_or(EXPECTED)?;
+        let requests = requests
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|requests| *requests > 0)
+            .ok_or(EXPECTED)?;
+        let period = match unit.trim() {
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(3600),
+            _ => return Err(EXPECTED.into()),
+        };
+        let limit = Self::new(requests, period);
+        match burst {
+            Some(burst) => {
+                let burst = burst
+                    .trim()
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|burst| *burst > 0)
+                    .ok_or(EXPECTED)?;
+                Ok(limit.with_burst(burst))
+            }
+            None => Ok(limit),
+        }
+    }
+}
+
+/// An address or CIDR block, ie `10.0.0.0/8` or `::1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    /// Whether the address is in this range. IPv4-mapped IPv6 addresses
+    /// are compared as IPv4.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(range) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(range) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|_| "expected an ip address or cidr block, ie 10.0.0.0/8")?
+            .to_canonical();
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("expected a prefix length of at most {max_prefix}"))?,
+            None => max_prefix,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// The address of the client that made the request.
+///
+/// This is the peer address unless the peer is one of the `trusted`
+/// proxies, in which case `X-Forwarded-For` is read from right to left and
+/// the first address that isn't a trusted proxy is the client. Addresses
+/// further left were written by the client and could be anything.
+pub fn client_ip(request: &Request, trusted: &[IpRange]) -> Option<IpAddr> {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|range| range.contains(ip));
+    let mut client = peer_addr(request)?.ip().to_canonical();
+    if !is_trusted(client) {
+        return Some(client);
+    }
+    if let Some(forwarded) = request.get_header("x-forwarded-for") {
+        for hop in forwarded.rsplit(',') {
+            let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = ip.to_canonical();
+            if !is_trusted(client) {
+                break;
+            }
+        }
+    }
+    Some(client)
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            updated: now,
+        }
+    }
+
+    /// Take a token, or return how long until one is available.
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> Result<(), Duration> {
+        let refilled = now.saturating_duration_since(self.updated).as_secs_f64()
+            / limit.refill_interval.as_secs_f64();
+        self.tokens = (self.tokens + refilled).min(limit.burst as f64);
+        self.updated = now;
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            Ok(())
+        } else {
+            Err(limit.refill_interval.mul_f64(1. - self.tokens))
+        }
+    }
+}
+
+/// Rate limits per route pattern and the buckets of recent clients.
+///
+/// Routes without their own limit use the default limit. Only the most
+/// recently seen clients are tracked, a forgotten client starts again with
+/// a full bucket.
+#[derive(Debug, Clone, Resource)]
+pub struct RateLimiter {
+    default_limit: Option<RateLimit>,
+    /// `None` exempts the route from rate limiting.
+    routes: HashMap<String, Option<RateLimit>>,
+    trusted_proxies: Vec<IpRange>,
+    buckets: LruCache<(String, IpAddr), Bucket>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        let mut limiter = Self {
+            default_limit: Some(RateLimit::per_second(10).with_burst(20)),
+            routes: default(),
+            trusted_proxies: default(),
+            buckets: LruCache::new(NonZeroUsize::new(10_000).unwrap()),
+        }
+        // probes and scrapers poll these, and they don't count visits
+        .without_route_limit("/healthz")
+        .without_route_limit("/readyz")
+        .without_route_limit("/metrics");
+        for pattern in GREETING_ROUTES {
+            // the `HELLO_RATE_LIMIT` default, `30/m+10`
+            limiter = limiter.with_route_limit(pattern, RateLimit::per_minute(30).with_burst(10));
+        }
+        limiter
+    }
+}
+
+impl RateLimiter {
+    /// Set the limit for routes without their own, `None` for no limit.
+    /// Defaults to 10 requests per second with bursts of 20.
+    pub fn with_default_limit(mut self, limit: Option<RateLimit>) -> Self {
+        self.default_limit = limit;
+        self
+    }
+
+    /// Set the limit for a route pattern, as passed to
+    /// [`ServerConfig::with_route`].
+    pub fn with_route_limit(mut self, pattern: &str, limit: RateLimit) -> Self {
+        self.routes.insert(pattern.to_string(), Some(limit));
+        self
+    }
+
+    /// Exempt a route pattern from rate limiting.
+    pub fn without_route_limit(mut self, pattern: &str) -> Self {
+        self.routes.insert(pattern.to_string(), None);
+        self
+    }
+
+    /// Set the reverse proxies whose `X-Forwarded-For` header is trusted,
+    /// see [`client_ip`]. Defaults to none.
+    pub fn with_trusted_proxies(mut self, proxies: impl IntoIterator<Item = IpRange>) -> Self {
+        self.trusted_proxies = proxies.into_iter().collect();
+        self
+    }
+
+    /// Set how many client and route buckets are kept, defaults to `10000`.
+    pub fn with_max_clients(mut self, max_clients: usize) -> Self {
+        self.buckets
+            .resize(NonZeroUsize::new(max_clients).unwrap_or(NonZeroUsize::MIN));
+        self
+    }
+
+    /// The limit applied to a route pattern, `None` if unlimited.
+    pub fn limit(&self, pattern: &str) -> Option<RateLimit> {
+        match self.routes.get(pattern) {
+            Some(limit) => *limit,
+            None => self.default_limit,
+        }
+    }
+
+    /// The reverse proxies whose `X-Forwarded-For` header is trusted.
+    pub fn trusted_proxies(&self) -> &[IpRange] {
+        &self.trusted_proxies
+    }
+
+    /// Take a token for the request to the route pattern, or return how
+    /// long the client should wait before retrying.
+    ///
+    /// Requests without a peer address, ie not from the [`HttpListener`],
+    /// are never limited.
+    pub fn check(&mut self, pattern: &str, request: &Request) -> Result<(), Duration> {
+        let Some(limit) = self.limit(pattern) else {
+            return Ok(());
+        };
+        let Some(client) = client_ip(request, &self.trusted_proxies) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        self.buckets
+            .get_or_insert_mut((pattern.to_string(), client), || Bucket::full(&limit, now))
+            .take(&limit, now)
+    }
+}
+
+/// A `429` asking the client to retry after the given time, in whole seconds.
+pub fn too_many_requests(retry_after: Duration) -> Response {
+    let secs = retry_after.as_secs_f64().ceil().max(1.) as u64;
+    Response::from_status_body(
+        http::StatusCode::TOO_MANY_REQUESTS.into(),
+        format!("Too Many Requests, retry in {secs}s"),
+        "text/plain",
+    )
+    .with_header("retry-after", &secs.to_string())
+}
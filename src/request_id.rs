@@ -0,0 +1,66 @@
+//! An ID for correlating a response with its log lines.
+//!
+//! Every request gets an `X-Request-Id`, keeping the one sent by the client
+//! or a load balancer if it is valid, see [`is_valid_request_id`]. The ID is
+//! on the [`request_span`] the handler logs in, in the
+//! [`AccessRecord`](crate::prelude::AccessRecord) and echoed on the
+//! response, so a user reporting a bad response can quote it.
+use beet::exports::bevy::log::tracing::Span;
+use beet::prelude::*;
+use ring::rand::SecureRandom;
+use ring::rand::SystemRandom;
+
+/// The request and response header carrying the ID.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longer inbound IDs are replaced rather than logged.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Whether an inbound ID is kept: at most 128 ascii letters, digits,
+/// `-`, `_` or `.`, which covers UUIDs and the IDs of common proxies
+/// without letting clients inject anything into the logs.
+pub fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.'))
+}
+
+/// A random version 4 UUID, ie `0b3f1d7e-5c2a-4f5e-9d8c-7a6b5c4d3e2f`.
+pub fn new_request_id() -> String {
+    let mut bytes = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("the system random number generator failed");
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// The request's `X-Request-Id` if valid, otherwise a new one replacing it.
+pub fn ensure_request_id(request: &mut Request) -> String {
+    if let Some(id) = request.get_header(REQUEST_ID_HEADER)
+        && is_valid_request_id(id)
+    {
+        return id.to_string();
+    }
+    let id = new_request_id();
+    let headers = request.request_parts_mut().headers_mut();
+    headers.remove(REQUEST_ID_HEADER);
+    headers.insert(REQUEST_ID_HEADER.to_string(), id.clone());
+    id
+}
+
+/// A span for logging while handling the request with this ID.
+pub fn request_span(id: &str) -> Span {
+    info_span!("request", id = %id)
+}
@@ -0,0 +1,330 @@
+//! Maps a method and path pattern to each [`RouteHandler`].
+//!
+//! Patterns are matched segment by segment:
+//!
+//! | Segment   | Matches                                          |
+//! |-----------|--------------------------------------------------|
+//! | `hello`   | exactly `hello`                                  |
+//! | `{name}`  | any single segment, captured as `name`           |
+//! | `{*rest}` | the remaining segments, if last, captured as `rest` |
+//!
+//! Captures are inserted into the request params, replacing any query
+//! parameter of the same name, so `/hello/{name}` and `/?name=` both
+//! reach the handler as `request.get_param("name")`. Like query values
+//! they stay percent-encoded, and a `+`, which is only a space in a query,
+//! is inserted as `%2B`.
+//!
+//! When several patterns match, the one with the most literal segments
+//! first wins, so `/hello/world` is preferred over `/hello/{name}`.
+//!
+//! A path matched only for other methods is a `405` listing them in
+//! `Allow`, except when the only match is a catch-all like `/{*path}`,
+//! which would otherwise turn every unknown path into a `405`.
+use crate::prelude::*;
+use beet::exports::http;
+use beet::prelude::*;
+
+/// Handles requests for a single route, with mutable access to the server entity.
+pub type RouteHandler = fn(&mut EntityWorldMut, Request) -> Response;
+
+/// A parsed path pattern, ie `/hello/{name}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutePattern {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Capture(String),
+    Rest(String),
+}
+
+impl Segment {
+    /// Lower is more specific.
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Literal(_) => 0,
+            Self::Capture(_) => 1,
+            Self::Rest(_) => 2,
+        }
+    }
+}
+
+impl RoutePattern {
+    /// Parse a pattern.
+    ///
+    /// # Panics
+    ///
+    /// If a capture is empty or unclosed, or a `{*rest}` capture is not
+    /// the last segment. Patterns are written in code, so these are bugs.
+    pub fn new(pattern: &str) -> Self {
+        let parts: Vec<&str> = pattern.split('/').filter(|part| !part.is_empty()).collect();
+        let segments = parts
+            .iter()
+            .enumerate()
+            .map(|(index, part)| {
+                let Some(inner) = part.strip_prefix('{') else {
+                    assert!(
+                        !part.contains(['{', '}']),
+                        "invalid route pattern {pattern:?}"
+                    );
+                    return Segment::Literal(part.to_string());
+                };
+                let name = inner
+                    .strip_suffix('}')
+                    .unwrap_or_else(|| panic!("unclosed capture in route pattern {pattern:?}"));
+                if let Some(name) = name.strip_prefix('*') {
+                    assert!(
+                        index == parts.len() - 1,
+                        "{{*{name}}} must be the last segment in route pattern {pattern:?}"
+                    );
+                    assert!(
+                        !name.is_empty(),
+                        "empty capture in route pattern {pattern:?}"
+                    );
+                    Segment::Rest(name.to_string())
+                } else {
+                    assert!(
+                        !name.is_empty(),
+                        "empty capture in route pattern {pattern:?}"
+                    );
+                    Segment::Capture(name.to_string())
+                }
+            })
+            .collect();
+        Self {
+            source: pattern.to_string(),
+            segments,
+        }
+    }
+
+    /// The pattern as written, used as the route label in
+    /// [`Metrics`](crate::prelude::Metrics).
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Match the path segments, returning the captures.
+    pub fn matches(&self, path: &[String]) -> Option<Vec<(String, String)>> {
+        let mut captures = Vec::new();
+        let mut path = path.iter();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => {
+                    if path.next()? != literal {
+                        return None;
+                    }
+                }
+                Segment::Capture(name) => {
+                    captures.push((name.clone(), path.next()?.clone()));
+                }
+                Segment::Rest(name) => {
+                    let rest = path.by_ref().cloned().collect::<Vec<_>>().join("/");
+                    captures.push((name.clone(), rest));
+                }
+            }
+        }
+        path.next().is_none().then_some(captures)
+    }
+
+    /// Whether the pattern is a single `{*rest}`, matching every path.
+    pub fn is_catch_all(&self) -> bool {
+        matches!(self.segments.as_slice(), [Segment::Rest(_)])
+    }
+
+    fn specificity(&self) -> Vec<u8> {
+        self.segments.iter().map(Segment::rank).collect()
+    }
+}
+
+/// A single method and pattern with its handler.
+#[derive(Debug, Clone)]
+pub struct Route {
+    method: HttpMethod,
+    pattern: RoutePattern,
+    handler: RouteHandler,
+}
+
+impl Route {
+    /// The method this route responds to.
+    pub fn method(&self) -> HttpMethod {
+        self.method
+    }
+
+    /// The path pattern of this route.
+    pub fn pattern(&self) -> &RoutePattern {
+        &self.pattern
+    }
+
+    /// `GET` routes also answer `HEAD` requests.
+    fn allows(&self, method: HttpMethod) -> bool {
+        self.method == method || (self.method == HttpMethod::Get && method == HttpMethod::Head)
+    }
+}
+
+/// The outcome of [`Router::lookup`].
+#[derive(Debug, Clone)]
+pub enum RouteLookup {
+    /// A route matched the method and path.
+    Found {
+        /// The handler to call.
+        handler: RouteHandler,
+        /// The matched pattern.
+        pattern: String,
+        /// Values of the `{name}` and `{*rest}` segments.
+        captures: Vec<(String, String)>,
+    },
+    /// The path matched but not for this method.
+    MethodNotAllowed {
+        /// The most specific matched pattern.
+        pattern: String,
+        /// The methods the path does allow.
+        allow: Vec<HttpMethod>,
+    },
+    /// No route matched the path.
+    NotFound,
+}
+
+impl RouteLookup {
+    /// The matched pattern, or `unmatched` if no route matched the path.
+    pub fn label(&self) -> &str {
+        match self {
+            Self::Found { pattern, .. } | Self::MethodNotAllowed { pattern, .. } => pattern,
+            // group unmatched paths so scanners can't create unbounded series
+            Self::NotFound => "unmatched",
+        }
+    }
+}
+
+/// A routing table of [`Route`]s.
+#[derive(Debug, Clone, Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    /// Add a route, replacing any existing handler for the same method and pattern.
+    ///
+    /// # Panics
+    ///
+    /// If the pattern is invalid, see [`RoutePattern::new`].
+    pub fn with_route(mut self, method: HttpMethod, pattern: &str, handler: RouteHandler) -> Self {
+        let pattern = RoutePattern::new(pattern);
+        self.routes
+            .retain(|route| !(route.method == method && route.pattern == pattern));
+        self.routes.push(Route {
+            method,
+            pattern,
+            handler,
+        });
+        self
+    }
+
+    /// Remove the routes for the given pattern, for every method.
+    pub fn without_pattern(mut self, pattern: &str) -> Self {
+        self.routes
+            .retain(|route| route.pattern.as_str() != pattern);
+        self
+    }
+
+    /// Remove all routes.
+    pub fn clear(&mut self) {
+        self.routes.clear();
+    }
+
+    /// All routes in the order they were added.
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// Find the most specific route for the method and path segments.
+    pub fn lookup(&self, method: HttpMethod, path: &[String]) -> RouteLookup {
+        let mut matched: Vec<(&Route, Vec<(String, String)>)> = self
+            .routes
+            .iter()
+            .filter_map(|route| Some((route, route.pattern.matches(path)?)))
+            .collect();
+        if matched.is_empty() {
+            return RouteLookup::NotFound;
+        }
+        // stable, so equally specific routes keep the order they were added
+        matched.sort_by_key(|(route, _)| route.pattern.specificity());
+
+        if let Some(index) = matched.iter().position(|(route, _)| route.allows(method)) {
+            let (route, captures) = matched.swap_remove(index);
+            return RouteLookup::Found {
+                handler: route.handler,
+                pattern: route.pattern.as_str().to_string(),
+                captures,
+            };
+        }
+
+        // a catch-all doesn't make a path known, so it is still not found
+        matched.retain(|(route, _)| !route.pattern.is_catch_all());
+        if matched.is_empty() {
+            return RouteLookup::NotFound;
+        }
+        let mut allow: Vec<HttpMethod> = matched.iter().map(|(route, _)| route.method).collect();
+        if allow.contains(&HttpMethod::Get) {
+            allow.push(HttpMethod::Head);
+        }
+        allow.sort();
+        allow.dedup();
+        RouteLookup::MethodNotAllowed {
+            pattern: matched[0].0.pattern.as_str().to_string(),
+            allow,
+        }
+    }
+
+    /// Dispatch the request to the matching route, returning the response
+    /// and the [`RouteLookup::label`].
+    pub fn handle(&self, server: &mut EntityWorldMut, request: Request) -> (Response, String) {
+        let lookup = self.lookup(*request.method(), request.path());
+        let label = lookup.label().to_string();
+        (Self::dispatch(lookup, server, request), label)
+    }
+
+    /// Respond to the request according to an earlier [`Router::lookup`].
+    pub fn dispatch(
+        lookup: RouteLookup,
+        server: &mut EntityWorldMut,
+        mut request: Request,
+    ) -> Response {
+        match lookup {
+            RouteLookup::Found {
+                handler, captures, ..
+            } => {
+                let params = request.params_mut();
+                for (name, value) in captures {
+                    params.remove(&name);
+                    params.insert(name, value.replace('+', "%2B"));
+                }
+                handler(server, request)
+            }
+            RouteLookup::MethodNotAllowed { allow, .. } => method_not_allowed(&allow),
+            RouteLookup::NotFound => not_found(&request),
+        }
+    }
+}
+
+/// A `404` naming the requested path, see [`error_response`].
+pub fn not_found(request: &Request) -> Response {
+    error_response(request, http::StatusCode::NOT_FOUND, &request.path_string())
+}
+
+/// A `405` with the `Allow` header listing the allowed methods.
+pub fn method_not_allowed(allow: &[HttpMethod]) -> Response {
+    let allow = allow
+        .iter()
+        .map(|method| method.into_http().as_str().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    Response::from_status_body(
+        StatusCode::MethodNotAllowed,
+        "Method Not Allowed",
+        "text/plain",
+    )
+    .with_header("allow", &allow)
+}
@@ -0,0 +1,333 @@
+//! The hello-lightsail http server as a reusable plugin.
+use crate::prelude::*;
+use beet::prelude::*;
+
+/// Host, port and routes for the server spawned by [`HelloLightsailPlugin`].
+///
+/// Insert this resource before adding the plugin to override the defaults:
+///
+/// ```ignore
+/// App::new()
+///     .insert_resource(ServerConfig::default().with_port(3000))
+///     .add_plugins((MinimalPlugins, HelloLightsailPlugin))
+///     .run();
+/// ```
+#[derive(Debug, Clone, Resource)]
+pub struct ServerConfig {
+    host: [u8; 4],
+    port: u16,
+    router: Router,
+    persist: bool,
+    max_names: usize,
+    tls: Option<TlsConfig>,
+    acme: Option<AcmeConfig>,
+    http_redirect_port: Option<u16>,
+    admin: Option<AdminConfig>,
+    static_files: Option<StaticFiles>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: [0, 0, 0, 0],
+            port: DEFAULT_SERVER_PORT,
+            router: Router::default()
+                .with_route(HttpMethod::Get, GREETING_ROUTES[0], greeting)
+                .with_route(HttpMethod::Get, GREETING_ROUTES[1], greeting)
+                .with_route(HttpMethod::Get, "/healthz", healthz)
+                .with_route(HttpMethod::Get, "/readyz", readyz)
+                .with_route(HttpMethod::Get, "/metrics", metrics)
+                .with_route(HttpMethod::Get, "/leaderboard", leaderboard)
+                .with_route(HttpMethod::Get, LIVE_COUNT_ROUTE, live_count)
+                .with_route(HttpMethod::Get, EVENTS_ROUTE, visit_events),
+            persist: true,
+            max_names: 1024,
+            tls: None,
+            acme: None,
+            http_redirect_port: None,
+            admin: None,
+            static_files: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Set the address to listen on, defaults to all interfaces.
+    pub fn with_host(mut self, host: [u8; 4]) -> Self {
+        self.host = host;
+        self
+    }
+
+    /// Set the port to listen on, defaults to `8337`.
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Add a route, replacing any existing handler for the same method and
+    /// pattern. See the [`Router`] for the pattern syntax.
+    pub fn with_route(mut self, method: HttpMethod, pattern: &str, handler: RouteHandler) -> Self {
+        self.router = self.router.with_route(method, pattern, handler);
+        self
+    }
+
+    /// Set whether the visitor [`Count`] is persisted by the [`StorePlugin`],
+    /// defaults to `true`.
+    pub fn with_persist(mut self, persist: bool) -> Self {
+        self.persist = persist;
+        self
+    }
+
+    /// Set how many visitor names are counted before the least recently
+    /// seen is forgotten, defaults to `1024`.
+    pub fn with_max_names(mut self, max_names: usize) -> Self {
+        self.max_names = max_names;
+        self
+    }
+
+    /// Serve HTTPS with the certificate and key files, reloaded on `SIGHUP`.
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Serve HTTPS with a certificate obtained and renewed from an ACME CA.
+    ///
+    /// This replaces any [`ServerConfig::with_tls`] files, and redirects
+    /// plain HTTP from port 80 unless [`ServerConfig::with_http_redirect`]
+    /// sets another port, since that is where HTTP-01 challenges arrive.
+    pub fn with_acme(mut self, acme: AcmeConfig) -> Self {
+        self.tls = Some(acme.tls());
+        self.acme = Some(acme);
+        self.http_redirect_port.get_or_insert(80);
+        self
+    }
+
+    /// Also listen for plain HTTP on this port, redirecting every request
+    /// to HTTPS. Only used with [`ServerConfig::with_tls`].
+    pub fn with_http_redirect(mut self, port: u16) -> Self {
+        self.http_redirect_port = Some(port);
+        self
+    }
+
+    /// Serve the admin routes, see [`AdminConfig`] for the token.
+    pub fn with_admin(mut self, admin: AdminConfig) -> Self {
+        self.router = self
+            .router
+            .with_route(HttpMethod::Get, "/admin/state", admin_state)
+            .with_route(HttpMethod::Put, "/admin/count", admin_set_count)
+            .with_route(HttpMethod::Delete, "/admin/count", admin_reset_count)
+            .with_route(HttpMethod::Put, "/admin/maintenance", admin_maintenance)
+            .with_route(HttpMethod::Put, "/admin/log-level", admin_log_level);
+        self.admin = Some(admin);
+        self
+    }
+
+    /// Serve the [`StaticFiles`] at any path no other route matches.
+    pub fn with_static_files(mut self, static_files: StaticFiles) -> Self {
+        self.router = self
+            .router
+            .with_route(HttpMethod::Get, STATIC_ROUTE, static_file);
+        self.static_files = Some(static_files);
+        self
+    }
+
+    /// Remove the routes for the given pattern, for every method.
+    pub fn without_route(mut self, pattern: &str) -> Self {
+        self.router = self.router.without_pattern(pattern);
+        self
+    }
+
+    /// Remove all routes, including the default greeting.
+    pub fn without_routes(mut self) -> Self {
+        self.router.clear();
+        self
+    }
+
+    /// The address to listen on.
+    pub fn host(&self) -> [u8; 4] {
+        self.host
+    }
+
+    /// The port to listen on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Whether the visitor [`Count`] is persisted.
+    pub fn persist(&self) -> bool {
+        self.persist
+    }
+
+    /// How many visitor names are counted, see [`NameCounts`].
+    pub fn max_names(&self) -> usize {
+        self.max_names
+    }
+
+    /// The certificate and key files if serving HTTPS.
+    pub fn tls(&self) -> Option<&TlsConfig> {
+        self.tls.as_ref()
+    }
+
+    /// Where the certificate is obtained from, if managed with ACME.
+    pub fn acme(&self) -> Option<&AcmeConfig> {
+        self.acme.as_ref()
+    }
+
+    /// The port redirecting plain HTTP to HTTPS, if any.
+    pub fn http_redirect_port(&self) -> Option<u16> {
+        self.http_redirect_port
+    }
+
+    /// The admin token and audit log, if the admin routes are served.
+    pub fn admin(&self) -> Option<&AdminConfig> {
+        self.admin.as_ref()
+    }
+
+    /// Where static files are served from, if they are.
+    pub fn static_files(&self) -> Option<&StaticFiles> {
+        self.static_files.as_ref()
+    }
+
+    /// The routing table.
+    pub fn router(&self) -> &Router {
+        &self.router
+    }
+}
+
+/// Spawns the hello-lightsail http server on [`Startup`], using the
+/// [`ServerConfig`] resource if present.
+///
+/// Unless disabled with [`ServerConfig::with_persist`], the visitor [`Count`]
+/// is persisted by the [`StorePlugin`]. On `SIGTERM` the [`ShutdownPlugin`]
+/// drains open connections before exiting.
+pub struct HelloLightsailPlugin;
+
+impl Plugin for HelloLightsailPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_plugin::<ServerPlugin>()
+            .init_plugin::<ShutdownPlugin>()
+            .init_plugin::<NotifyPlugin>()
+            .init_plugin::<TlsPlugin>()
+            .init_resource::<ServerConfig>()
+            .init_resource::<Metrics>()
+            .init_resource::<AccessLog>()
+            .init_resource::<RateLimiter>()
+            .init_resource::<AcmeChallenges>()
+            .init_resource::<Maintenance>()
+            .init_resource::<FailedAuthAudit>()
+            .init_resource::<Compression>()
+            .init_resource::<Catalogs>()
+            .init_resource::<LiveCount>()
+            .init_resource::<VisitEvents>()
+            .add_systems(Startup, spawn_server);
+        if app.world().resource::<ServerConfig>().persist {
+            app.init_plugin::<StorePlugin>();
+        }
+    }
+}
+
+fn spawn_server(
+    mut commands: Commands,
+    mut exit: MessageWriter<AppExit>,
+    config: Res<ServerConfig>,
+    challenges: Res<AcmeChallenges>,
+) {
+    let mut listener = HttpListener::new((config.host, config.port));
+    if let Some(tls) = &config.tls {
+        let certificate = match &config.acme {
+            Some(acme) => acme
+                .ensure_certificate()
+                .and_then(|()| TlsCertificate::load(tls.clone()))
+                .and_then(|certificate| {
+                    spawn_acme_renewal(acme.clone(), certificate.clone(), challenges.clone())?;
+                    Ok(certificate)
+                }),
+            None => TlsCertificate::load(tls.clone()),
+        };
+        match certificate {
+            Ok(certificate) => listener = listener.with_tls(certificate),
+            Err(err) => {
+                // serving plain HTTP instead would be a surprise
+                error!("{}", err);
+                exit.write(AppExit::from_code(CONFIG_EXIT_CODE));
+                return;
+            }
+        }
+    }
+    // spawned first so it takes the socket inherited from systemd
+    commands.spawn((
+        listener,
+        Count::default(),
+        NameCounts::new(config.max_names),
+        handler_exchange(handler),
+    ));
+    if let (Some(_), Some(port)) = (&config.tls, config.http_redirect_port) {
+        let mut redirect = HttpsRedirect::new(config.port);
+        if config.acme.is_some() {
+            redirect = redirect.with_route(HttpMethod::Get, ACME_CHALLENGE_ROUTE, acme_challenge);
+        }
+        commands.spawn((
+            HttpListener::new((config.host, port)),
+            redirect,
+            handler_exchange(https_redirect),
+        ));
+    }
+}
+
+/// Dispatches each request to the matching [`ServerConfig`] route unless
+/// the route is off for [`Maintenance`] or the [`RateLimiter`] throttles it.
+/// Responses are compressed if the client accepts it, see [`Compression`],
+/// and recorded by [`observe_exchange`].
+fn handler(mut server: EntityWorldMut, request: Request) -> Response {
+    observe_exchange(&mut server, request, |server, request| {
+        let accept_encoding = request.get_header("accept-encoding").map(String::from);
+        let lookup = server
+            .resource::<ServerConfig>()
+            .router()
+            .lookup(*request.method(), request.path());
+        let route_label = lookup.label().to_string();
+        let response = if !server.resource::<Maintenance>().allows(&route_label) {
+            under_maintenance(&request)
+        } else {
+            match server
+                .resource_mut::<RateLimiter>()
+                .check(&route_label, &request)
+            {
+                Ok(()) => Router::dispatch(lookup, server, request),
+                Err(retry_after) => {
+                    server.resource_mut::<Metrics>().throttle(&route_label);
+                    too_many_requests(retry_after)
+                }
+            }
+        };
+        let response = server
+            .resource::<Compression>()
+            .compress(accept_encoding.as_deref(), response);
+        (response, route_label)
+    })
+}
+
+/// Answer the request with `respond`, which returns the response and its
+/// route label, recording the outcome in the [`Metrics`] and [`AccessLog`].
+/// Every response carries the request ID, see [`ensure_request_id`].
+///
+/// Shared by the handlers of every [`HttpListener`] the server spawns.
+pub(crate) fn observe_exchange(
+    server: &mut EntityWorldMut,
+    mut request: Request,
+    respond: impl FnOnce(&mut EntityWorldMut, Request) -> (Response, String),
+) -> Response {
+    let start = Instant::now();
+    let request_id = ensure_request_id(&mut request);
+    let _span = request_span(&request_id).entered();
+    let record = AccessRecord::new(&request);
+    let (response, route_label) = respond(server, request);
+    let elapsed = start.elapsed();
+    let record = record.finish(&response, elapsed);
+    server
+        .resource_mut::<Metrics>()
+        .observe(&record.method, &route_label, record.status, elapsed);
+    server.resource::<AccessLog>().write(&record);
+    response.with_header(REQUEST_ID_HEADER, &request_id)
+}
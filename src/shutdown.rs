@@ -0,0 +1,134 @@
+//! Graceful shutdown on `SIGTERM` and `SIGINT`.
+//!
+//! When systemd stops the service the [`HttpListener`] stops accepting,
+//! open connections finish their in-flight request and the app exits once
+//! they have drained or the [`ShutdownConfig::drain_timeout`] elapses.
+//! The [`StorePlugin`] then saves the final count on [`AppExit`].
+//!
+//! A second signal while draining exits immediately.
+use crate::prelude::*;
+use beet::prelude::*;
+use signal_hook::consts::SIGINT;
+use signal_hook::consts::SIGTERM;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+/// How long to wait for open connections on shutdown.
+#[derive(Debug, Clone, Resource)]
+pub struct ShutdownConfig {
+    drain_timeout: Duration,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl ShutdownConfig {
+    /// Set how long to wait for in-flight requests, defaults to 10 seconds.
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// How long to wait for in-flight requests before exiting anyway.
+    pub fn drain_timeout(&self) -> Duration {
+        self.drain_timeout
+    }
+}
+
+/// Set by the signal handlers, or manually with [`ShutdownRequest::request`].
+#[derive(Debug, Clone, Default, Resource)]
+pub struct ShutdownRequest(Arc<AtomicBool>);
+
+impl ShutdownRequest {
+    /// Begin a graceful shutdown, as if a `SIGTERM` was received.
+    pub fn request(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether a shutdown has been requested.
+    pub fn is_requested(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Triggered once when the server starts draining connections.
+#[derive(Debug, Clone, Event)]
+pub struct ShutdownStarted;
+
+/// Present while draining, with the time at which to give up.
+#[derive(Debug, Resource)]
+struct Draining {
+    deadline: Instant,
+}
+
+/// Drains the [`HttpListener`] connections and exits on `SIGTERM` or `SIGINT`.
+#[derive(Default)]
+pub struct ShutdownPlugin;
+
+impl Plugin for ShutdownPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ShutdownConfig>()
+            .init_resource::<ShutdownRequest>()
+            .init_resource::<Connections>()
+            .add_systems(Update, (begin_drain, finish_drain).chain());
+
+        let flag = app.world().resource::<ShutdownRequest>().0.clone();
+        for signal in [SIGTERM, SIGINT] {
+            // registered first, so a second signal sees the flag already set
+            let registered =
+                signal_hook::flag::register_conditional_shutdown(signal, 1, flag.clone())
+                    .and_then(|_| signal_hook::flag::register(signal, flag.clone()));
+            if let Err(err) = registered {
+                warn!("Failed to register handler for signal {}: {}", signal, err);
+            }
+        }
+    }
+}
+
+fn begin_drain(
+    mut commands: Commands,
+    request: Res<ShutdownRequest>,
+    config: Res<ShutdownConfig>,
+    connections: Res<Connections>,
+    draining: Option<Res<Draining>>,
+) {
+    if draining.is_some() || !request.is_requested() {
+        return;
+    }
+    info!(
+        "Shutting down, draining {} open connections",
+        connections.active()
+    );
+    connections.stop();
+    commands.insert_resource(Draining {
+        deadline: Instant::now() + config.drain_timeout,
+    });
+    commands.trigger(ShutdownStarted);
+}
+
+fn finish_drain(
+    draining: Option<Res<Draining>>,
+    connections: Res<Connections>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    let Some(draining) = draining else {
+        return;
+    };
+    let active = connections.active();
+    if active == 0 {
+        info!("All connections drained");
+        exit.write(AppExit::Success);
+    } else if Instant::now() >= draining.deadline {
+        warn!(
+            "Drain timeout elapsed, abandoning {} open connections",
+            active
+        );
+        exit.write(AppExit::Success);
+    }
+}
@@ -0,0 +1,508 @@
+//! Files served from the public directory, ie `/robots.txt`.
+//!
+//! [`StaticFiles`] serves a directory on disk, or with the `embed-public`
+//! feature the crate's `public/` directory compiled into the binary, at
+//! [`STATIC_ROUTE`]. The more specific routes, like the greeting at `/`,
+//! take precedence.
+//!
+//! - Paths with `..`, hidden segments or symlinks out of the directory are
+//!   not found, so nothing outside it can be read.
+//! - The `Content-Type` is guessed from the extension, and a directory
+//!   serves its `index.html`.
+//! - Responses carry an `ETag` and, if known, a `Last-Modified` date,
+//!   answering `If-None-Match` and `If-Modified-Since` with
+//!   `304 Not Modified`. The `ETag` of a file on disk is of its size and
+//!   modification time, so it is known without reading the file.
+//! - A single `Range` of bytes is answered with `206 Partial Content`,
+//!   honoring `If-Range`. Only the range is read from disk.
+//! - Bodies of at least [`STATIC_STREAM_MIN_BYTES`] on disk aren't read
+//!   into memory. [`static_file`] marks the response with
+//!   [`STATIC_FILE_MARKER`] and the [`HttpListener`] streams the
+//!   [`StaticStream`] in chunks, so they aren't compressed on the fly
+//!   either.
+//! - A precompressed sibling, ie `app.js.br`, is served instead if the
+//!   client accepts it, see [`Compression::precompressed`].
+use crate::prelude::*;
+use beet::exports::futures_lite;
+use beet::exports::futures_lite::AsyncReadExt;
+use beet::exports::futures_lite::Stream;
+use beet::exports::http;
+use beet::prelude::*;
+use blocking::Unblock;
+use bytes::Bytes;
+use percent_encoding::NON_ALPHANUMERIC;
+use percent_encoding::percent_decode_str;
+use percent_encoding::utf8_percent_encode;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// The route pattern static files are served from, matching any path.
+pub const STATIC_ROUTE: &str = "/{*path}";
+
+/// Set by [`static_file`] to the [`StaticStream`] the [`HttpListener`]
+/// removes and sends as the body, ie `0-1048575 %2Fopt%2Fpublic%2Fa%2Ebin`.
+pub const STATIC_FILE_MARKER: &str = "x-static-file";
+
+/// Bodies read from disk at least this large are streamed, see
+/// [`STATIC_FILE_MARKER`].
+pub const STATIC_STREAM_MIN_BYTES: u64 = 1 << 20;
+
+/// How much of a [`StaticStream`] is read at a time.
+const STATIC_STREAM_CHUNK_BYTES: u64 = 64 << 10;
+
+/// The crate's `public/` directory, compiled in by the `embed-public` feature.
+#[cfg(feature = "embed-public")]
+static EMBEDDED_PUBLIC: include_dir::Dir<'static> =
+    include_dir::include_dir!("$CARGO_MANIFEST_DIR/public");
+
+/// Where static files are read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticFiles {
+    source: StaticSource,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum StaticSource {
+    Dir(PathBuf),
+    #[cfg(feature = "embed-public")]
+    Embedded,
+}
+
+/// A file found in [`StaticFiles`], read only as far as a response needs.
+#[derive(Debug, Clone)]
+struct StaticFile {
+    body: StaticBody,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone)]
+enum StaticBody {
+    Path(PathBuf),
+    #[cfg(feature = "embed-public")]
+    Embedded(&'static [u8]),
+}
+
+impl StaticFile {
+    /// A strong `ETag`, ie `"1b-187e0f3c2a1d9e00"` for a file on disk, or
+    /// `"1f2e3d4c5b6a7988"` of the contents of an embedded one.
+    fn etag(&self) -> String {
+        match &self.body {
+            StaticBody::Path(_) => {
+                let nanos = self
+                    .modified
+                    .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+                    .map(|since| since.as_nanos())
+                    .unwrap_or_default();
+                format!("\"{:x}-{:x}\"", self.len, nanos)
+            }
+            #[cfg(feature = "embed-public")]
+            StaticBody::Embedded(contents) => content_etag(contents),
+        }
+    }
+
+    /// The response with the inclusive byte range as its body, or the whole
+    /// file for `None`. A large range of a file on disk is only marked for
+    /// the listener to stream, with the `Content-Length` it will have.
+    fn respond(&self, response: Response, range: Option<(u64, u64)>) -> io::Result<Response> {
+        let Some((start, end)) = range.or_else(|| Some((0, self.len.checked_sub(1)?))) else {
+            return Ok(response);
+        };
+        // a path that isn't UTF-8 can't be put in the marker, so is read
+        if let StaticBody::Path(path) = &self.body
+            && end - start + 1 >= STATIC_STREAM_MIN_BYTES
+            && let Some(path) = path.to_str()
+        {
+            let stream = StaticStream {
+                path: path.into(),
+                start,
+                end,
+            };
+            return Ok(response
+                .with_header(STATIC_FILE_MARKER, &stream.marker())
+                .with_header("content-length", &stream.content_length().to_string()));
+        }
+        Ok(response.with_body(self.read_range(start, end)?))
+    }
+
+    /// The inclusive byte range, seeking past the rest of the file.
+    fn read_range(&self, start: u64, end: u64) -> io::Result<Bytes> {
+        match &self.body {
+            StaticBody::Path(path) => {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(start))?;
+                let mut contents = vec![0; (end - start + 1) as usize];
+                // fails rather than sending less than `Content-Range` says,
+                // if the file was truncated since it was found
+                file.read_exact(&mut contents)?;
+                Ok(contents.into())
+            }
+            #[cfg(feature = "embed-public")]
+            StaticBody::Embedded(contents) => {
+                Ok(Bytes::from_static(&contents[start as usize..=end as usize]))
+            }
+        }
+    }
+}
+
+impl StaticFiles {
+    /// Serve the files in this directory, which is read on every request
+    /// so files can be changed without a restart.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            source: StaticSource::Dir(dir.into()),
+        }
+    }
+
+    /// Serve the crate's `public/` directory as it was when the binary was
+    /// built, so a deploy is a single file.
+    #[cfg(feature = "embed-public")]
+    pub fn embedded() -> Self {
+        Self {
+            source: StaticSource::Embedded,
+        }
+    }
+
+    /// The directory files are served from, `None` if they are embedded.
+    pub fn dir(&self) -> Option<&Path> {
+        match &self.source {
+            StaticSource::Dir(dir) => Some(dir),
+            #[cfg(feature = "embed-public")]
+            StaticSource::Embedded => None,
+        }
+    }
+
+    /// The path of the `index.html` if the relative path is a directory.
+    fn resolve(&self, relative: PathBuf) -> PathBuf {
+        let is_dir = match &self.source {
+            StaticSource::Dir(dir) => dir.join(&relative).is_dir(),
+            #[cfg(feature = "embed-public")]
+            StaticSource::Embedded => {
+                relative.as_os_str().is_empty() || EMBEDDED_PUBLIC.get_dir(&relative).is_some()
+            }
+        };
+        if is_dir {
+            relative.join("index.html")
+        } else {
+            relative
+        }
+    }
+
+    /// Find the file at the relative path, `None` if it doesn't exist or
+    /// resolves to outside the directory.
+    fn find(&self, relative: &Path) -> io::Result<Option<StaticFile>> {
+        match &self.source {
+            StaticSource::Dir(dir) => {
+                let canonical = |path: &Path| match path.canonicalize() {
+                    Ok(path) => Ok(Some(path)),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+                    Err(err) => Err(err),
+                };
+                let (Some(root), Some(path)) = (canonical(dir)?, canonical(&dir.join(relative))?)
+                else {
+                    return Ok(None);
+                };
+                // symlinks are followed, but only within the directory
+                if !path.starts_with(&root) || !path.is_file() {
+                    return Ok(None);
+                }
+                let metadata = std::fs::metadata(&path)?;
+                Ok(Some(StaticFile {
+                    len: metadata.len(),
+                    modified: metadata.modified().ok(),
+                    body: StaticBody::Path(path),
+                }))
+            }
+            #[cfg(feature = "embed-public")]
+            StaticSource::Embedded => {
+                Ok(EMBEDDED_PUBLIC.get_file(relative).map(|file| StaticFile {
+                    body: StaticBody::Embedded(file.contents()),
+                    len: file.contents().len() as u64,
+                    modified: file.metadata().map(|metadata| metadata.modified()),
+                }))
+            }
+        }
+    }
+}
+
+/// An inclusive byte range of a file on disk, sent by the [`HttpListener`]
+/// for a response marked with [`STATIC_FILE_MARKER`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticStream {
+    path: PathBuf,
+    start: u64,
+    end: u64,
+}
+
+impl StaticStream {
+    /// Parse the value of a [`STATIC_FILE_MARKER`].
+    pub fn from_marker(marker: &str) -> Option<Self> {
+        let (range, path) = marker.split_once(' ')?;
+        let (start, end) = range.split_once('-')?;
+        let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+        if end < start {
+            return None;
+        }
+        let path = percent_decode_str(path).decode_utf8().ok()?;
+        Some(Self {
+            path: PathBuf::from(path.as_ref()),
+            start,
+            end,
+        })
+    }
+
+    fn marker(&self) -> String {
+        let path = self.path.to_str().unwrap_or_default();
+        let path = utf8_percent_encode(path, NON_ALPHANUMERIC);
+        format!("{}-{} {}", self.start, self.end, path)
+    }
+
+    /// The number of bytes streamed.
+    pub fn content_length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Read the range in chunks off the async executor. Ends with an error
+    /// rather than sending less than the `Content-Length`, if the file was
+    /// removed or truncated since the response was marked.
+    pub fn into_stream(self) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+        let remaining = self.content_length();
+        futures_lite::stream::unfold(
+            (Some(self), None::<Unblock<File>>, remaining),
+            |(stream, file, remaining)| async move {
+                if remaining == 0 {
+                    return None;
+                }
+                let mut file = match (file, stream) {
+                    (Some(file), _) => file,
+                    (None, Some(stream)) => match blocking::unblock(move || {
+                        let mut file = File::open(&stream.path)?;
+                        file.seek(SeekFrom::Start(stream.start))?;
+                        Ok::<_, io::Error>(file)
+                    })
+                    .await
+                    {
+                        Ok(file) => Unblock::new(file),
+                        Err(err) => return Some((Err(err), (None, None, 0))),
+                    },
+                    (None, None) => return None,
+                };
+                let mut chunk = vec![0; remaining.min(STATIC_STREAM_CHUNK_BYTES) as usize];
+                match file.read(&mut chunk).await {
+                    Ok(0) => Some((Err(io::ErrorKind::UnexpectedEof.into()), (None, None, 0))),
+                    Ok(len) => {
+                        chunk.truncate(len);
+                        Some((Ok(chunk.into()), (None, Some(file), remaining - len as u64)))
+                    }
+                    Err(err) => Some((Err(err), (None, None, 0))),
+                }
+            },
+        )
+    }
+}
+
+/// The relative file path for the `{*path}` capture, `None` if any segment
+/// could escape the directory or is hidden, ie `..` or `.env`.
+pub fn static_path(path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        let segment = percent_decode_str(segment).decode_utf8().ok()?;
+        if segment.starts_with('.') || segment.contains(['/', '\\', '\0']) {
+            return None;
+        }
+        relative.push(segment.as_ref());
+    }
+    Some(relative)
+}
+
+/// The `Content-Type` for the file's extension, `application/octet-stream`
+/// if unknown, with a `utf-8` charset for text.
+pub fn static_content_type(path: &Path) -> String {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    let is_text = mime.type_() == mime_guess::mime::TEXT
+        || matches!(
+            mime.essence_str(),
+            "application/javascript" | "application/json"
+        );
+    if is_text {
+        format!("{}; charset=utf-8", mime.essence_str())
+    } else {
+        mime.essence_str().to_string()
+    }
+}
+
+/// `GET /{*path}`, the file at the path in the [`StaticFiles`].
+pub fn static_file(server: &mut EntityWorldMut, request: Request) -> Response {
+    let Some(files) = server.resource::<ServerConfig>().static_files().cloned() else {
+        return not_found(&request);
+    };
+    let Some(relative) = request.get_param("path").and_then(static_path) else {
+        return not_found(&request);
+    };
+    let relative = files.resolve(relative);
+    let read_failed = |err: io::Error| {
+        error!("Failed to read static file {}: {}", relative.display(), err);
+        error_response(
+            &request,
+            http::StatusCode::INTERNAL_SERVER_ERROR,
+            "the file could not be read",
+        )
+    };
+    let file = match files.find(&relative) {
+        Ok(Some(file)) => file,
+        Ok(None) => return not_found(&request),
+        Err(err) => return read_failed(err),
+    };
+    let content_type = static_content_type(&relative);
+
+    // ranges are of the file itself, never of a compressed variant
+    let range = request.get_header("range");
+    let compression = server.resource::<Compression>();
+    let precompressed = match range {
+        Some(_) => None,
+        None => compression.precompressed(request.get_header("accept-encoding"), |encoding| {
+            let mut variant = relative.clone().into_os_string();
+            variant.push(encoding.extension());
+            files.find(Path::new(&variant)).ok().flatten()
+        }),
+    };
+    let (encoding, file) = match precompressed {
+        Some((encoding, variant)) => (
+            Some(encoding),
+            StaticFile {
+                modified: file.modified,
+                ..variant
+            },
+        ),
+        None => (None, file),
+    };
+
+    let etag = file.etag();
+    let last_modified = file.modified.map(httpdate::fmt_http_date);
+    let with_validators = |mut response: Response| {
+        response = response
+            .with_header("etag", &etag)
+            .with_header("accept-ranges", "bytes");
+        if let Some(last_modified) = &last_modified {
+            response = response.with_header("last-modified", last_modified);
+        }
+        if let Some(encoding) = encoding {
+            response = response.with_header("content-encoding", encoding.as_str());
+        }
+        if compression.enabled() {
+            response = with_vary(response, "accept-encoding");
+        }
+        response
+    };
+
+    if is_not_modified(&request, &etag, file.modified) {
+        return with_validators(Response::from_status(http::StatusCode::NOT_MODIFIED.into()));
+    }
+    let length = file.len;
+    let range = range.filter(|_| if_range_matches(&request, &etag, last_modified.as_deref()));
+    match range.map(|range| parse_range(range, length)) {
+        Some(Some(Ok((start, end)))) => {
+            let response = Response::from_status(http::StatusCode::PARTIAL_CONTENT.into())
+                .with_content_type(&content_type)
+                .with_header("content-range", &format!("bytes {start}-{end}/{length}"));
+            match file.respond(response, Some((start, end))) {
+                Ok(response) => with_validators(response),
+                Err(err) => read_failed(err),
+            }
+        }
+        Some(Some(Err(()))) => with_validators(
+            Response::from_status_body(
+                http::StatusCode::RANGE_NOT_SATISFIABLE.into(),
+                "Range Not Satisfiable",
+                "text/plain",
+            )
+            .with_header("content-range", &format!("bytes */{length}")),
+        ),
+        // a missing, malformed or multipart range gets the whole file
+        Some(None) | None => match file.respond(Response::ok_body("", &content_type), None) {
+            Ok(response) => with_validators(response),
+            Err(err) => read_failed(err),
+        },
+    }
+}
+
+/// A strong `ETag` of the contents, ie `"1f2e3d4c5b6a7988"`.
+#[cfg(feature = "embed-public")]
+fn content_etag(contents: &[u8]) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, contents);
+    let hex: String = digest.as_ref()[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("\"{hex}\"")
+}
+
+/// Whether the client's cached copy is current, by `If-None-Match` or,
+/// only without it, `If-Modified-Since`.
+fn is_not_modified(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = request.get_header("if-none-match") {
+        // weak comparison, so a compressed variant's `W/` tag matches too
+        let etag = etag.trim_start_matches("W/");
+        return if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+    let since = request
+        .get_header("if-modified-since")
+        .and_then(|date| httpdate::parse_http_date(date).ok());
+    match (since, modified) {
+        // the header has second precision
+        (Some(since), Some(modified)) => httpdate::HttpDate::from(modified) <= since.into(),
+        _ => false,
+    }
+}
+
+/// Whether a `Range` applies, ie there is no `If-Range` or it names the
+/// current version by strong `ETag` or exact `Last-Modified` date.
+fn if_range_matches(request: &Request, etag: &str, last_modified: Option<&str>) -> bool {
+    match request.get_header("if-range").map(str::trim) {
+        None => true,
+        Some(validator) if validator.starts_with('"') => validator == etag,
+        Some(validator) => last_modified == Some(validator),
+    }
+}
+
+/// Parse a single `bytes=` range into inclusive offsets, `None` to ignore
+/// the header and `Err` if no byte of the range exists.
+fn parse_range(header: &str, length: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    let range = if start.is_empty() {
+        // a suffix, ie the last 500 bytes
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 {
+            return Some(Err(()));
+        }
+        (length.saturating_sub(suffix), length.checked_sub(1)?)
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end = match end {
+            "" => u64::MAX,
+            end => end.parse().ok()?,
+        };
+        if end < start {
+            return None;
+        }
+        (start, end.min(length.saturating_sub(1)))
+    };
+    if range.0 >= length {
+        return Some(Err(()));
+    }
+    Some(Ok(range))
+}
@@ -0,0 +1,299 @@
+//! Durable storage for server state.
+//!
+//! The visitor [`Count`] and [`NameCounts`] only live in memory, so without this every
+//! restart of the systemd service would reset it to zero. The [`StorePlugin`]
+//! loads it from disk on startup, writes it back periodically and performs
+//! a final flush when the app exits.
+//!
+//! A state file that can't be parsed is moved aside to a timestamped
+//! `.corrupt` file and the count starts over. If it can't be read at all,
+//! ie because of its permissions, the app exits with [`IO_EXIT_CODE`]
+//! rather than overwrite it on the next flush.
+use crate::prelude::*;
+use beet::prelude::*;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Exit code for a state file that can't be read, `EX_IOERR` from
+/// `sysexits.h`.
+pub const IO_EXIT_CODE: u8 = 74;
+
+/// The number of visitors greeted so far.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Component)]
+pub struct Count(pub u32);
+
+/// The on-disk representation of the server state.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersistedState {
+    /// The visitor count at the time of the last flush.
+    pub count: u32,
+    /// Visits per normalized name, least recently visited first.
+    #[serde(default)]
+    pub names: Vec<(String, u32)>,
+}
+
+/// Location and flush policy for the persisted server state.
+///
+/// Relative paths resolve against the working directory, which for the
+/// deployed service is `/opt/hello-lightsail`.
+#[derive(Debug, Clone, Resource)]
+pub struct StateStore {
+    path: PathBuf,
+    flush_interval: Duration,
+}
+
+impl Default for StateStore {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("state.json"),
+            flush_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+impl StateStore {
+    /// Set the file the state is read from and written to.
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    /// Set how often changes are written back to disk.
+    pub fn with_flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// The file the state is read from and written to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// How often changes are written back to disk.
+    pub fn flush_interval(&self) -> Duration {
+        self.flush_interval
+    }
+
+    /// Read the state from disk, returning the default state if the
+    /// file does not exist yet.
+    ///
+    /// A file that isn't valid state is an [`std::io::ErrorKind::InvalidData`]
+    /// error, any other error is from reading it.
+    pub fn load(&self) -> std::io::Result<PersistedState> {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(PersistedState::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Write the state to disk.
+    ///
+    /// The state is written to a temporary file in the same directory which
+    /// is synced and then renamed over the target, so a crash mid-write
+    /// leaves either the old or the new state, never a truncated file.
+    pub fn save(&self, state: &PersistedState) -> std::io::Result<()> {
+        let dir = self.dir();
+        std::fs::create_dir_all(dir)?;
+        let tmp_path = self.tmp_path();
+        {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(&serde_json::to_vec_pretty(state)?)?;
+            file.sync_all()?;
+        }
+        std::fs::rename(&tmp_path, &self.path)?;
+        // sync the directory so the rename itself is durable
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+
+    /// Check that the state directory accepts writes by creating
+    /// and removing a probe file next to the state file.
+    pub fn check_writable(&self) -> std::io::Result<()> {
+        let mut file_name = self.path.file_name().unwrap_or_default().to_owned();
+        file_name.push(".probe");
+        let probe = self.path.with_file_name(file_name);
+        File::create(&probe)?.write_all(b"ok")?;
+        std::fs::remove_file(&probe)
+    }
+
+    fn dir(&self) -> &Path {
+        match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        }
+    }
+
+    /// Where an unparseable state file is moved, ie
+    /// `state.json.1760000000.corrupt`, so earlier backups are kept.
+    fn corrupt_path(&self) -> PathBuf {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut file_name = self.path.file_name().unwrap_or_default().to_owned();
+        file_name.push(format!(".{secs}.corrupt"));
+        self.path.with_file_name(file_name)
+    }
+
+    fn tmp_path(&self) -> PathBuf {
+        let mut file_name = self.path.file_name().unwrap_or_default().to_owned();
+        file_name.push(".tmp");
+        self.path.with_file_name(file_name)
+    }
+}
+
+/// Loads the [`Count`] on startup and keeps the [`StateStore`] up to date.
+///
+/// Insert a [`StateStore`] resource before adding the plugin to override
+/// the default path and flush interval.
+#[derive(Default)]
+pub struct StorePlugin;
+
+impl Plugin for StorePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StateStore>();
+        let flush_interval = app.world().resource::<StateStore>().flush_interval;
+        app.insert_resource(FlushTimer(Timer::new(flush_interval, TimerMode::Repeating)))
+            .init_resource::<Flushed>()
+            .init_resource::<StoreStatus>()
+            .add_systems(PostStartup, load_state)
+            .add_systems(Update, flush_state)
+            .add_systems(Last, flush_on_exit);
+    }
+}
+
+/// Tracks whether the persisted state has been loaded yet.
+#[derive(Debug, Default, Clone, Resource)]
+pub struct StoreStatus {
+    loaded: bool,
+}
+
+impl StoreStatus {
+    /// Whether the state has been read from disk, after which the
+    /// [`Count`] reflects the persisted value.
+    pub fn loaded(&self) -> bool {
+        self.loaded
+    }
+}
+
+#[derive(Resource)]
+struct FlushTimer(Timer);
+
+/// The state as of the last successful load or save.
+#[derive(Default, Resource)]
+struct Flushed(PersistedState);
+
+type StateQuery<'w, 's> = Query<'w, 's, (&'static Count, Option<&'static NameCounts>)>;
+
+fn current_state(counts: &StateQuery) -> Option<PersistedState> {
+    counts.iter().next().map(|(count, names)| PersistedState {
+        count: count.0,
+        names: names.map(NameCounts::to_vec).unwrap_or_default(),
+    })
+}
+
+fn save_if_changed(
+    store: &StateStore,
+    status: &StoreStatus,
+    flushed: &mut Flushed,
+    counts: &StateQuery,
+) {
+    // never replace a file that wasn't loaded
+    if !status.loaded {
+        return;
+    }
+    let Some(state) = current_state(counts) else {
+        return;
+    };
+    if state == flushed.0 {
+        return;
+    }
+    match store.save(&state) {
+        Ok(()) => flushed.0 = state,
+        Err(err) => error!("Failed to save state to {}: {}", store.path.display(), err),
+    }
+}
+
+fn load_state(
+    store: Res<StateStore>,
+    mut status: ResMut<StoreStatus>,
+    mut flushed: ResMut<Flushed>,
+    mut counts: Query<(&mut Count, Option<&mut NameCounts>)>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    let state = match store.load() {
+        Ok(state) => state,
+        Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+            // keep the unparseable file around instead of overwriting it
+            // with a fresh count on the next flush
+            let backup = store.corrupt_path();
+            error!(
+                "Failed to parse state from {}, moving it to {}: {}",
+                store.path.display(),
+                backup.display(),
+                err
+            );
+            if let Err(err) = std::fs::rename(&store.path, &backup) {
+                error!("Failed to move {}: {}", store.path.display(), err);
+                exit.write(AppExit::from_code(IO_EXIT_CODE));
+                return;
+            }
+            PersistedState::default()
+        }
+        Err(err) => {
+            // the file may be fine, so exit before a flush overwrites it
+            error!(
+                "Failed to read state from {}: {}",
+                store.path.display(),
+                err
+            );
+            exit.write(AppExit::from_code(IO_EXIT_CODE));
+            return;
+        }
+    };
+    info!(
+        "Loaded visitor count {} from {}",
+        state.count,
+        store.path.display()
+    );
+    for (mut count, names) in counts.iter_mut() {
+        count.0 = state.count;
+        if let Some(mut names) = names {
+            names.restore(state.names.iter().cloned());
+        }
+    }
+    flushed.0 = state;
+    status.loaded = true;
+}
+
+fn flush_state(
+    time: Res<Time>,
+    (store, status): (Res<StateStore>, Res<StoreStatus>),
+    mut timer: ResMut<FlushTimer>,
+    mut flushed: ResMut<Flushed>,
+    counts: StateQuery,
+) {
+    if timer.0.tick(time.delta()).just_finished() {
+        save_if_changed(&store, &status, &mut flushed, &counts);
+    }
+}
+
+fn flush_on_exit(
+    mut exit: MessageReader<AppExit>,
+    (store, status): (Res<StateStore>, Res<StoreStatus>),
+    mut flushed: ResMut<Flushed>,
+    counts: StateQuery,
+) {
+    if exit.read().next().is_some() {
+        save_if_changed(&store, &status, &mut flushed, &counts);
+    }
+}
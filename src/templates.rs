@@ -0,0 +1,96 @@
+//! Server-rendered HTML pages, built from beet rsx templates.
+//!
+//! Every page is wrapped in the same `Layout`. beet writes text into the
+//! page as is, so each template escapes the values it is given with
+//! [`EscapeHtml`], which matters for anything taken from a request, like
+//! the visitor's name or the path of a missing page.
+//!
+//! Rendering builds a small beet app each time, so the HTML representations
+//! are slower to produce than the text and JSON ones.
+use crate::prelude::*;
+use beet::exports::http;
+use beet::prelude::*;
+
+/// Representations of error responses, text first for `curl`.
+const ERROR_TYPES: [MediaType; 2] = [MediaType::Text, MediaType::Html];
+
+/// The document every page is rendered into, with the page as its body.
+#[template]
+fn Layout(title: String, lang: String) -> impl Bundle {
+    let title = EscapeHtml::escape(&title);
+    rsx! {
+        <!DOCTYPE html>
+        <html lang=lang>
+            <head>
+                <meta charset="utf-8" />
+                <meta name="viewport" content="width=device-width, initial-scale=1" />
+                <link rel="icon" href="/favicon.ico" />
+                <title>{title}</title>
+            </head>
+            <body>
+                <slot />
+            </body>
+        </html>
+    }
+}
+
+#[template]
+fn GreetingPage(greeting: Greeting, text: GreetingText) -> impl Bundle {
+    let heading = EscapeHtml::escape(&text.salutation);
+    let visitor = EscapeHtml::escape(&text.visitor);
+    let (before, after) = (
+        EscapeHtml::escape(&text.hint.0),
+        EscapeHtml::escape(&text.hint.1),
+    );
+    rsx! {
+        <Layout title=text.salutation lang=greeting.locale.to_string()>
+            <h1>{heading}</h1>
+            <p>{visitor}</p>
+            <p>{before}<code>name</code>{after}</p>
+        </Layout>
+    }
+}
+
+#[template]
+fn ErrorPage(status: u16, reason: String, detail: String) -> impl Bundle {
+    let heading = format!("{status} {reason}");
+    let detail = EscapeHtml::escape(&detail);
+    rsx! {
+        <Layout title=heading.clone() lang=DEFAULT_LOCALE.to_string()>
+            <h1>{heading}</h1>
+            <p>{detail}</p>
+            <p><a href="/">"back to the greeting"</a></p>
+        </Layout>
+    }
+}
+
+/// The HTML page for the greeting, in its locale.
+pub fn greeting_page(greeting: &Greeting, text: &GreetingText) -> String {
+    HtmlFragment::parse_bundle(rsx! {
+        <GreetingPage greeting=greeting.clone() text=text.clone() />
+    })
+}
+
+/// The HTML page for an error, ie `404 Not Found` with the path as detail.
+pub fn error_page(status: http::StatusCode, detail: &str) -> String {
+    let reason = status.canonical_reason().unwrap_or("Error").to_string();
+    HtmlFragment::parse_bundle(rsx! {
+        <ErrorPage status=status.as_u16() reason=reason detail=detail.to_string() />
+    })
+}
+
+/// An error response, as text like `Not Found: /nope`, or as an
+/// [`error_page`] for clients that prefer HTML, ie browsers.
+pub fn error_response(request: &Request, status: http::StatusCode, detail: &str) -> Response {
+    let media_type = negotiate(request, &ERROR_TYPES).unwrap_or(MediaType::Text);
+    let body = match media_type {
+        MediaType::Html => error_page(status, detail),
+        _ => format!(
+            "{}: {}",
+            status.canonical_reason().unwrap_or("Error"),
+            detail
+        ),
+    };
+    let response = Response::from_status_body(status.into(), body, media_type.content_type());
+    with_vary(response, "accept")
+}
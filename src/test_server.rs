@@ -0,0 +1,249 @@
+//! An in-process server for integration tests.
+//!
+//! [`TestServer`] builds the same app as `examples/server.rs` from an
+//! [`AppConfig`], minus logging, listening on an ephemeral port. Requests
+//! are dispatched straight to the server entity's exchange handler rather
+//! than over a socket, so tests neither race the listener nor each other
+//! for ports.
+//!
+//! Streams, upgrades and shutdown need a real connection, for which
+//! [`TestServer::listen`] runs the app on a thread of its own and waits
+//! until it is [`Listening`], again on an ephemeral port.
+//!
+//! ```ignore
+//! let mut server = TestServer::new();
+//! let response = server.get("/?name=pete");
+//! assert_eq!(response.status(), 200);
+//! assert!(response.text().contains("hello pete"));
+//! ```
+use crate::prelude::*;
+use beet::exports::futures_lite::future::block_on;
+use beet::exports::http;
+use beet::prelude::*;
+use bytes::Bytes;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+/// How long to wait for a [`ListeningServer`] to bind, and for its
+/// connections to answer.
+const LISTEN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The hello-lightsail app with a client for making requests to it.
+pub struct TestServer {
+    app: App,
+    server: Entity,
+}
+
+impl Default for TestServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestServer {
+    /// A server with the default configuration, as if no environment
+    /// variables were set.
+    pub fn new() -> Self {
+        Self::from_config(AppConfig::from_vars(|_| None).expect("the default config is valid"))
+    }
+
+    /// A server with the given configuration, except that it listens on an
+    /// ephemeral localhost port and neither persists state nor writes the
+    /// access log.
+    ///
+    /// # Panics
+    ///
+    /// If the server entity was not spawned, ie the TLS certificate failed
+    /// to load.
+    pub fn from_config(config: AppConfig) -> Self {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, test_config(config)));
+        // runs `Startup`, which spawns the server
+        app.update();
+        let server = app
+            .world_mut()
+            .query_filtered::<Entity, (With<HttpListener>, With<Count>)>()
+            .single(app.world())
+            .expect("the server was not spawned");
+        Self { app, server }
+    }
+
+    /// Send a `GET` request for the path, which may include a query string.
+    pub fn get(&mut self, path: &str) -> TestResponse {
+        self.send(Request::get(path))
+    }
+
+    /// Send the request to the server and wait for the response.
+    ///
+    /// The request has no peer address, so it is never rate limited.
+    pub fn send(&mut self, request: Request) -> TestResponse {
+        let response = block_on(
+            self.app
+                .world_mut()
+                .entity_mut(self.server)
+                .exchange(request),
+        );
+        let (parts, body) = response.into_parts();
+        let body = block_on(body.into_bytes()).expect("failed to read the response body");
+        TestResponse { parts, body }
+    }
+
+    /// The current visitor count.
+    pub fn count(&self) -> u32 {
+        self.app
+            .world()
+            .get::<Count>(self.server)
+            .map(|count| count.0)
+            .unwrap_or(0)
+    }
+
+    /// The app, ie to run more updates or inspect resources.
+    pub fn app_mut(&mut self) -> &mut App {
+        &mut self.app
+    }
+
+    /// Run the app for the configuration on a thread of its own, with the
+    /// same overrides as [`TestServer::from_config`], and wait until it is
+    /// listening.
+    pub fn listen(config: AppConfig) -> ListeningServer {
+        let config = test_config(config);
+        Self::spawn(move |app| {
+            app.add_plugins((MinimalPlugins, config));
+        })
+    }
+
+    /// Run the app built by `build` on a thread of its own and wait until
+    /// its first [`HttpListener`] is [`Listening`], which should bind port
+    /// `0`.
+    ///
+    /// A [`ShutdownRequest`] is inserted before `build`, for
+    /// [`ListeningServer::shutdown`].
+    ///
+    /// # Panics
+    ///
+    /// If the app exits or doesn't listen within 5 seconds.
+    pub fn spawn(build: impl FnOnce(&mut App) + Send + 'static) -> ListeningServer {
+        let shutdown = ShutdownRequest::default();
+        let (sender, listening) = mpsc::channel();
+        let thread = std::thread::spawn({
+            let shutdown = shutdown.clone();
+            move || {
+                let mut app = App::new();
+                app.insert_resource(shutdown).add_systems(
+                    Update,
+                    move |listeners: Query<&Listening, Added<Listening>>| {
+                        for Listening(addr) in listeners.iter() {
+                            sender.send(*addr).ok();
+                        }
+                    },
+                );
+                build(&mut app);
+                app.run()
+            }
+        });
+        let addr = listening
+            .recv_timeout(LISTEN_TIMEOUT)
+            .unwrap_or_else(|err| panic!("the server did not start listening: {}", err));
+        ListeningServer {
+            addr,
+            shutdown,
+            thread,
+        }
+    }
+}
+
+/// Listen on an ephemeral localhost port, and neither persist state nor
+/// write the access log.
+fn test_config(mut config: AppConfig) -> AppConfig {
+    config.server = config
+        .server
+        .with_host([127, 0, 0, 1])
+        .with_port(0)
+        .with_persist(false);
+    config.access_log = config.access_log.with_format(AccessLogFormat::Off);
+    config
+}
+
+/// An app started by [`TestServer::spawn`], running until it is shut down.
+pub struct ListeningServer {
+    addr: SocketAddr,
+    shutdown: ShutdownRequest,
+    thread: JoinHandle<AppExit>,
+}
+
+impl ListeningServer {
+    /// The address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Open a connection to the server, whose reads time out after
+    /// 5 seconds rather than hang the test.
+    pub fn connect(&self) -> TcpStream {
+        let stream = TcpStream::connect(self.addr).expect("failed to connect to the server");
+        stream
+            .set_read_timeout(Some(LISTEN_TIMEOUT))
+            .expect("failed to set the read timeout");
+        stream
+    }
+
+    /// Begin a graceful shutdown, as if a `SIGTERM` was received.
+    pub fn shutdown(&self) {
+        self.shutdown.request();
+    }
+
+    /// Wait for the app to exit.
+    ///
+    /// # Panics
+    ///
+    /// If the app panicked.
+    pub fn join(self) -> AppExit {
+        self.thread.join().expect("the server panicked")
+    }
+}
+
+/// A response received by a [`TestServer`], with the body read.
+#[derive(Debug)]
+pub struct TestResponse {
+    parts: ResponseParts,
+    body: Bytes,
+}
+
+impl TestResponse {
+    /// The status code, ie `404`.
+    pub fn status(&self) -> u16 {
+        http::StatusCode::from(self.parts.status()).as_u16()
+    }
+
+    /// The first value of a header, by lowercase name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.parts.get_header(name)
+    }
+
+    /// The body.
+    pub fn bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The body as text.
+    ///
+    /// # Panics
+    ///
+    /// If the body is not utf-8, ie compressed.
+    pub fn text(&self) -> &str {
+        std::str::from_utf8(&self.body)
+            .unwrap_or_else(|err| panic!("the body is not utf-8: {}", err))
+    }
+
+    /// The body parsed as JSON.
+    ///
+    /// # Panics
+    ///
+    /// If the body is not valid JSON.
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body)
+            .unwrap_or_else(|err| panic!("invalid json {:?}: {}", self.text(), err))
+    }
+}
@@ -0,0 +1,273 @@
+//! HTTPS for the [`HttpListener`] with rustls.
+//!
+//! The certificate chain and private key are read from PEM files when the
+//! server starts, and again on `SIGHUP`, ie `systemctl reload`. Handshakes
+//! after a reload use the new certificate while open connections carry on,
+//! so renewing a certificate never drops a request.
+//!
+//! Browsers and links still default to plain HTTP, which an [`HttpsRedirect`]
+//! listener answers with a redirect to the HTTPS port.
+use crate::prelude::*;
+use beet::exports::http;
+use beet::prelude::*;
+use futures_rustls::TlsAcceptor;
+use futures_rustls::pki_types::CertificateDer;
+use futures_rustls::pki_types::PrivateKeyDer;
+use futures_rustls::pki_types::pem::PemObject;
+use futures_rustls::rustls;
+use futures_rustls::rustls::server::ClientHello;
+use futures_rustls::rustls::server::ResolvesServerCert;
+use futures_rustls::rustls::sign::CertifiedKey;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+/// The PEM files a [`TlsCertificate`] is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+}
+
+impl TlsConfig {
+    /// Read the certificate chain and private key from these PEM files.
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        }
+    }
+
+    /// The certificate chain, leaf first.
+    pub fn cert_path(&self) -> &Path {
+        &self.cert_path
+    }
+
+    /// The private key of the leaf certificate.
+    pub fn key_path(&self) -> &Path {
+        &self.key_path
+    }
+}
+
+/// A certificate that can be replaced while the listener is serving.
+///
+/// Clones share the certificate, so reloading one reloads them all.
+#[derive(Debug, Clone)]
+pub struct TlsCertificate {
+    config: TlsConfig,
+    current: Arc<RwLock<Arc<CertifiedKey>>>,
+}
+
+impl TlsCertificate {
+    /// Read the certificate and key, failing if either is missing, invalid
+    /// or they don't belong together.
+    pub fn load(config: TlsConfig) -> std::io::Result<Self> {
+        let current = read_certified_key(&config)?;
+        Ok(Self {
+            config,
+            current: Arc::new(RwLock::new(Arc::new(current))),
+        })
+    }
+
+    /// Read the files again, keeping the current certificate if they
+    /// are invalid.
+    pub fn reload(&self) -> std::io::Result<()> {
+        let certified_key = read_certified_key(&self.config)?;
+        *self.current.write().unwrap() = Arc::new(certified_key);
+        Ok(())
+    }
+
+    /// The files the certificate is read from.
+    pub fn config(&self) -> &TlsConfig {
+        &self.config
+    }
+
+    /// An acceptor that always presents the current certificate.
+    pub(crate) fn acceptor(&self) -> Result<TlsAcceptor, rustls::Error> {
+        let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(CertResolver(self.current.clone())));
+        // the listener only speaks HTTP/1
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+#[derive(Debug)]
+struct CertResolver(Arc<RwLock<Arc<CertifiedKey>>>);
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.read().unwrap().clone())
+    }
+}
+
+fn read_certified_key(config: &TlsConfig) -> std::io::Result<CertifiedKey> {
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+    let certs = CertificateDer::pem_file_iter(&config.cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| {
+            invalid(format!(
+                "Failed to read certificates from {}: {}",
+                config.cert_path.display(),
+                err
+            ))
+        })?;
+    if certs.is_empty() {
+        return Err(invalid(format!(
+            "No certificates found in {}",
+            config.cert_path.display()
+        )));
+    }
+    let key = PrivateKeyDer::from_pem_file(&config.key_path).map_err(|err| {
+        invalid(format!(
+            "Failed to read private key from {}: {}",
+            config.key_path.display(),
+            err
+        ))
+    })?;
+    // also checks that the key matches the leaf certificate
+    CertifiedKey::from_der(certs, key, &rustls::crypto::ring::default_provider()).map_err(|err| {
+        invalid(format!(
+            "Invalid certificate {} or key {}: {}",
+            config.cert_path.display(),
+            config.key_path.display(),
+            err
+        ))
+    })
+}
+
+/// Reloads the [`TlsCertificate`] of every [`HttpListener`] on `SIGHUP`.
+#[derive(Default)]
+pub struct TlsPlugin;
+
+impl Plugin for TlsPlugin {
+    fn build(&self, app: &mut App) {
+        let reload = Arc::new(AtomicBool::new(false));
+        if let Err(err) = signal_hook::flag::register(signal_hook::consts::SIGHUP, reload.clone()) {
+            warn!("Failed to register SIGHUP handler: {}", err);
+        }
+        app.insert_resource(ReloadRequest(reload))
+            .add_systems(Update, reload_certificates);
+    }
+}
+
+#[derive(Resource)]
+struct ReloadRequest(Arc<AtomicBool>);
+
+fn reload_certificates(request: Res<ReloadRequest>, listeners: Query<&HttpListener>) {
+    if !request.0.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let mut reloaded = false;
+    for certificate in listeners.iter().filter_map(HttpListener::tls) {
+        reloaded = true;
+        match certificate.reload() {
+            Ok(()) => info!(
+                "Reloaded certificate from {}",
+                certificate.config().cert_path().display()
+            ),
+            Err(err) => error!("Keeping the current certificate: {}", err),
+        }
+    }
+    if !reloaded {
+        info!("Received SIGHUP but no listener uses TLS");
+    }
+}
+
+/// Answers requests on a plain HTTP listener with a permanent redirect to
+/// the same path on the HTTPS port, unless they match one of its own routes.
+#[derive(Debug, Clone, Component)]
+pub struct HttpsRedirect {
+    port: u16,
+    router: Router,
+}
+
+impl HttpsRedirect {
+    /// Redirect to HTTPS on this port.
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            router: default(),
+        }
+    }
+
+    /// Serve a route over plain HTTP instead of redirecting it,
+    /// ie [`ACME_CHALLENGE_ROUTE`].
+    pub fn with_route(mut self, method: HttpMethod, pattern: &str, handler: RouteHandler) -> Self {
+        self.router = self.router.with_route(method, pattern, handler);
+        self
+    }
+
+    /// The port HTTPS is served on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+/// Exchange handler for an [`HttpsRedirect`] entity.
+///
+/// Redirects are counted in the [`Metrics`] under the `https_redirect`
+/// route, and its own routes under their pattern.
+pub fn https_redirect(mut server: EntityWorldMut, request: Request) -> Response {
+    observe_exchange(&mut server, request, |server, request| {
+        let Some(redirect) = server.get::<HttpsRedirect>().cloned() else {
+            return (not_found(&request), "unmatched".into());
+        };
+        match redirect.router.lookup(*request.method(), request.path()) {
+            lookup @ RouteLookup::Found { .. } => {
+                let label = lookup.label().to_string();
+                (Router::dispatch(lookup, server, request), label)
+            }
+            _ => (
+                redirect_response(&request, redirect.port),
+                "https_redirect".into(),
+            ),
+        }
+    })
+}
+
+fn redirect_response(request: &Request, port: u16) -> Response {
+    match request.get_header("host").map(host_without_port) {
+        Some(host) if !host.is_empty() => {
+            let port = if port == 443 {
+                String::new()
+            } else {
+                format!(":{port}")
+            };
+            let query = request.query_string();
+            let query = if query.is_empty() {
+                query
+            } else {
+                format!("?{query}")
+            };
+            let location = format!("https://{host}{port}{}{query}", request.path_string());
+            Response::from_status_body(
+                http::StatusCode::PERMANENT_REDIRECT.into(),
+                format!("Moved to {location}"),
+                "text/plain",
+            )
+            .with_header("location", &location)
+        }
+        _ => Response::from_status_body(
+            http::StatusCode::BAD_REQUEST.into(),
+            "Bad Request: missing Host header",
+            "text/plain",
+        ),
+    }
+}
+
+/// Strip the port from a `Host` header, keeping IPv6 brackets.
+fn host_without_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        // a colon inside brackets is part of an IPv6 address
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    }
+}
@@ -0,0 +1,135 @@
+//! The [`AccessRecord`] written for every request, in both formats, and the
+//! log level that can be changed at runtime.
+use beet::exports::http;
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+use serde_json::Value;
+
+fn record() -> AccessRecord {
+    let request = Request::get("/hello?name=pete")
+        .with_header(PEER_ADDR_HEADER, "203.0.113.7:51234")
+        .with_header("user-agent", "curl/8.5.0")
+        .with_header(REQUEST_ID_HEADER, "lb-1234");
+    AccessRecord::new(&request).finish(
+        &Response::ok_body("hello pete", "text/plain"),
+        Duration::from_micros(1500),
+    )
+}
+
+#[test]
+fn records_the_exchange() {
+    let record = record();
+    assert_eq!(record.method, "GET");
+    assert_eq!(record.path, "/hello");
+    assert_eq!(record.query.as_deref(), Some("name=pete"));
+    assert_eq!(record.status, 200);
+    assert_eq!(record.latency_ms, 1.5);
+    assert_eq!(record.bytes, Some(10));
+    assert_eq!(record.client_ip.as_deref(), Some("203.0.113.7"));
+    assert_eq!(record.user_agent.as_deref(), Some("curl/8.5.0"));
+    assert_eq!(record.request_id.as_deref(), Some("lb-1234"));
+
+    // ie 2024-01-31T12:00:00.000Z
+    let timestamp = record.timestamp.as_bytes();
+    assert_eq!(timestamp.len(), 24, "{}", record.timestamp);
+    for (index, separator) in [(4, b'-'), (7, b'-'), (10, b'T'), (13, b':'), (19, b'.')] {
+        assert_eq!(timestamp[index], separator, "{}", record.timestamp);
+    }
+    assert!(record.timestamp.ends_with('Z'));
+
+    let record = AccessRecord::new(&Request::post("/admin/flush")).finish(
+        &Response::from_status(http::StatusCode::UNAUTHORIZED.into()),
+        Duration::ZERO,
+    );
+    assert_eq!(record.method, "POST");
+    assert_eq!(record.query, None);
+    assert_eq!(record.status, 401);
+    assert_eq!(record.client_ip, None);
+}
+
+#[test]
+fn writes_one_json_object_per_line() {
+    let line = serde_json::to_string(&record()).unwrap();
+    assert!(!line.contains('\n'));
+    let json: Value = serde_json::from_str(&line).unwrap();
+    for field in [
+        "timestamp",
+        "method",
+        "path",
+        "query",
+        "status",
+        "latency_ms",
+        "bytes",
+        "client_ip",
+        "user_agent",
+        "request_id",
+    ] {
+        assert!(json.get(field).is_some(), "missing {field} in {line}");
+    }
+    assert_eq!(json["status"], 200);
+    assert_eq!(json["client_ip"], "203.0.113.7");
+}
+
+#[test]
+fn writes_a_readable_line() {
+    let record = record();
+    assert_eq!(
+        record.to_string(),
+        format!(
+            "{} 203.0.113.7 GET /hello?name=pete 200 10B 1.50ms \"curl/8.5.0\" lb-1234",
+            record.timestamp
+        )
+    );
+    let record = AccessRecord::new(&Request::get("/")).finish(
+        &Response::ok_body("", "text/plain"),
+        Duration::from_millis(2),
+    );
+    assert!(
+        record
+            .to_string()
+            .ends_with(" - GET / 200 0B 2.00ms \"-\" -"),
+        "{record}"
+    );
+}
+
+#[test]
+fn selects_the_format() {
+    assert_eq!("json".parse(), Ok(AccessLogFormat::Json));
+    assert_eq!("TEXT".parse(), Ok(AccessLogFormat::Text));
+    assert_eq!("off".parse(), Ok(AccessLogFormat::Off));
+    assert!("yaml".parse::<AccessLogFormat>().is_err());
+
+    let config = |format: &str| {
+        let format = format.to_string();
+        AppConfig::from_vars(move |key| (key == "HELLO_ACCESS_LOG").then(|| format.clone()))
+    };
+    assert_eq!(
+        AppConfig::from_vars(|_| None).unwrap().access_log.format(),
+        AccessLogFormat::Json
+    );
+    assert_eq!(
+        config("text").unwrap().access_log.format(),
+        AccessLogFormat::Text
+    );
+    assert!(config("yaml").is_err());
+}
+
+#[test]
+fn changes_the_log_level_at_runtime() {
+    // the level is global, so this is the only test touching it
+    let config =
+        AppConfig::from_vars(|key| (key == "HELLO_LOG_LEVEL").then(|| "warn".into())).unwrap();
+    assert_eq!(config.log_level, Level::WARN);
+    config.log_plugin();
+    let expected = if std::env::var_os("RUST_LOG").is_some() {
+        Level::TRACE
+    } else {
+        Level::WARN
+    };
+    assert_eq!(log_level(), expected);
+
+    set_log_level(Level::DEBUG);
+    assert_eq!(log_level(), Level::DEBUG);
+    set_log_level(Level::ERROR);
+    assert_eq!(log_level(), Level::ERROR);
+}
@@ -0,0 +1,70 @@
+//! Sockets inherited from systemd, used even when bound elsewhere than
+//! configured, and only when announced for this process.
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+
+fn addr(addr: &str) -> SocketAddr {
+    addr.parse().unwrap()
+}
+
+#[test]
+fn matches_the_configured_address() {
+    let inherited = addr("127.0.0.1:8337");
+    assert!(InheritedSockets::serves(addr("0.0.0.0:8337"), inherited));
+    assert!(InheritedSockets::serves(addr("127.0.0.1:8337"), inherited));
+    assert!(InheritedSockets::serves(addr("127.0.0.1:0"), inherited));
+    assert!(!InheritedSockets::serves(addr("0.0.0.0:8080"), inherited));
+    assert!(!InheritedSockets::serves(addr("10.0.0.1:8337"), inherited));
+    assert!(InheritedSockets::serves(
+        addr("[::]:8337"),
+        addr("[::1]:8337")
+    ));
+}
+
+#[test]
+fn only_takes_sockets_for_this_process() {
+    let pid = std::process::id().to_string();
+    let other_pid = (std::process::id() + 1).to_string();
+    let vars = |pid: &str, fds: &str| {
+        let (pid, fds) = (pid.to_string(), fds.to_string());
+        move |key: &str| match key {
+            "LISTEN_PID" => Some(pid.clone()),
+            "LISTEN_FDS" => Some(fds.clone()),
+            _ => None,
+        }
+    };
+    // a parent's sockets, which were meant for it rather than us
+    assert!(InheritedSockets::from_vars(vars(&other_pid, "1")).is_empty());
+    assert!(InheritedSockets::from_vars(vars(&pid, "0")).is_empty());
+    assert!(InheritedSockets::from_vars(vars(&pid, "-1")).is_empty());
+    assert!(InheritedSockets::from_vars(vars(&pid, "many")).is_empty());
+    assert!(InheritedSockets::from_vars(|_| None).is_empty());
+}
+
+#[test]
+fn serves_the_inherited_socket() {
+    let socket = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let inherited = socket.local_addr().unwrap();
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .init_plugin::<ServerPlugin>()
+        .insert_resource(InheritedSockets::new([socket]));
+    // configured for another port, which is warned about
+    let listener = app
+        .world_mut()
+        .spawn((
+            HttpListener::new(([127, 0, 0, 1], 1)),
+            handler_exchange(|_, _| Response::ok_body("ok", "text/plain")),
+        ))
+        .id();
+    app.update();
+    assert_eq!(
+        app.world()
+            .get::<Listening>(listener)
+            .map(|listening| listening.0),
+        Some(inherited)
+    );
+    assert!(app.world().resource::<InheritedSockets>().is_empty());
+}
@@ -0,0 +1,180 @@
+//! The `/admin` routes: bearer token checks, the audit log, and the actions
+//! themselves.
+use beet::exports::futures_lite::StreamExt;
+use beet::exports::futures_lite::future;
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+use serde_json::Value;
+use std::path::Path;
+use std::path::PathBuf;
+use std::pin::pin;
+
+const TOKEN: &str = "correct-horse-battery";
+
+fn server(name: &str) -> (TestServer, PathBuf) {
+    let audit_log =
+        std::env::temp_dir().join(format!("hello-admin-{}-{}.log", name, std::process::id()));
+    std::fs::remove_file(&audit_log).ok();
+    (server_with_audit_log(&audit_log), audit_log)
+}
+
+fn server_with_audit_log(audit_log: &Path) -> TestServer {
+    let path = audit_log.display().to_string();
+    let config = AppConfig::from_vars(|key| match key {
+        "HELLO_ADMIN_TOKEN" => Some(TOKEN.into()),
+        "HELLO_AUDIT_LOG" => Some(path.clone()),
+        "HELLO_PERSIST" => Some("false".into()),
+        _ => None,
+    })
+    .unwrap();
+    TestServer::from_config(config)
+}
+
+fn admin(method: HttpMethod, path: &str) -> Request {
+    Request::new(method, path).with_header("authorization", &format!("Bearer {TOKEN}"))
+}
+
+fn audit_records(audit_log: &PathBuf) -> Vec<Value> {
+    std::fs::read_to_string(audit_log)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn rejects_a_missing_or_wrong_token() {
+    let (mut server, audit_log) = server("unauthorized");
+    for request in [
+        Request::get("/admin/state"),
+        Request::get("/admin/state").with_header("authorization", "Bearer not-the-token"),
+        Request::get("/admin/state").with_header("authorization", &format!("Basic {TOKEN}")),
+        Request::new(HttpMethod::Put, "/admin/count?value=7"),
+    ] {
+        let response = server.send(request);
+        assert_eq!(response.status(), 401);
+        assert_eq!(
+            response.header("www-authenticate"),
+            Some("Bearer realm=\"admin\"")
+        );
+    }
+    assert_eq!(server.count(), 0);
+
+    // only the first of a burst of failures is recorded
+    let records = audit_records(&audit_log);
+    assert_eq!(records.len(), 1, "{records:?}");
+    assert_eq!(records[0]["action"], "dump_state");
+    assert_eq!(records[0]["status"], 401);
+    assert!(records[0].get("result").is_none());
+}
+
+#[test]
+fn records_admin_actions() {
+    let (mut server, audit_log) = server("audit");
+    let response = server.send(
+        admin(HttpMethod::Put, "/admin/count?value=41")
+            .with_header(REQUEST_ID_HEADER, "audit-1")
+            .with_header("user-agent", "curl/8"),
+    );
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.json()["count"], 41);
+    assert_eq!(server.count(), 41);
+    server.get("/");
+    assert_eq!(server.count(), 42);
+
+    let response = server.send(admin(HttpMethod::Put, "/admin/count?value=-1"));
+    assert_eq!(response.status(), 400);
+    let response = server.send(admin(HttpMethod::Get, "/admin/state"));
+    assert_eq!(response.json()["count"], 42);
+    let response = server.send(admin(HttpMethod::Delete, "/admin/count"));
+    assert_eq!(response.json()["previous"], 42);
+    assert_eq!(server.count(), 0);
+
+    let records = audit_records(&audit_log);
+    let actions: Vec<_> = records
+        .iter()
+        .map(|record| {
+            (
+                record["action"].as_str().unwrap(),
+                record["status"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            ("set_count", 200),
+            ("set_count", 400),
+            ("dump_state", 200),
+            ("reset_count", 200),
+        ]
+    );
+    assert_eq!(records[0]["request_id"], "audit-1");
+    assert_eq!(records[0]["user_agent"], "curl/8");
+    assert_eq!(records[0]["query"], "value=41");
+    assert_eq!(records[0]["result"]["count"], 41);
+    // reads are recorded without their result
+    assert!(records[2].get("result").is_none());
+}
+
+#[test]
+fn refuses_actions_it_cannot_record() {
+    // a directory can't be opened for appending, and /dev/full can't be
+    // written to
+    let mut audit_logs = vec![std::env::temp_dir()];
+    if cfg!(target_os = "linux") {
+        audit_logs.push("/dev/full".into());
+    }
+    for audit_log in audit_logs {
+        let mut server = server_with_audit_log(&audit_log);
+        for request in [
+            admin(HttpMethod::Put, "/admin/count?value=7"),
+            admin(HttpMethod::Put, "/admin/maintenance?enabled=true"),
+            admin(HttpMethod::Get, "/admin/state"),
+        ] {
+            let response = server.send(request);
+            assert_eq!(response.status(), 503, "{}", audit_log.display());
+            assert_eq!(response.json()["error"], "audit log unavailable");
+        }
+        // nothing was applied
+        assert_eq!(server.get("/").status(), 200);
+        assert_eq!(server.count(), 1);
+    }
+}
+
+#[test]
+fn publishes_the_new_count() {
+    let (mut server, _) = server("publish");
+    let response = server.send(admin(HttpMethod::Put, "/admin/count?value=4294967295"));
+    assert_eq!(response.status(), 200);
+    let live = server.app_mut().world().resource::<LiveCount>().clone();
+    assert_eq!(live.count(), u32::MAX);
+    let events = server.app_mut().world().resource::<VisitEvents>().clone();
+    let stream = events.stream(Some(0), Connections::default());
+    let replay = future::block_on(pin!(stream).next()).unwrap();
+    let replay = String::from_utf8(replay.to_vec()).unwrap();
+    assert!(replay.contains("data: {\"count\":4294967295}"), "{replay}");
+
+    // the next visit can't count any higher
+    assert_eq!(server.get("/").status(), 200);
+    assert_eq!(server.count(), u32::MAX);
+}
+
+#[test]
+fn toggles_maintenance() {
+    let (mut server, _) = server("maintenance");
+    let response = server.send(admin(HttpMethod::Put, "/admin/maintenance?enabled=true"));
+    assert_eq!(response.status(), 200);
+    assert_eq!(server.get("/").status(), 503);
+    assert_eq!(server.get("/healthz").status(), 200);
+    assert_eq!(server.count(), 0);
+
+    let response = server.send(admin(HttpMethod::Get, "/admin/state"));
+    assert_eq!(response.json()["maintenance"], true);
+    let response = server.send(admin(HttpMethod::Put, "/admin/maintenance?enabled=maybe"));
+    assert_eq!(response.status(), 400);
+
+    server.send(admin(HttpMethod::Put, "/admin/maintenance?enabled=false"));
+    assert_eq!(server.get("/").status(), 200);
+    assert_eq!(server.count(), 1);
+}
@@ -0,0 +1,110 @@
+//! `Accept-Encoding` negotiation, exercised through the [`TestServer`].
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+use std::io::Read;
+
+/// A server whose leaderboard is long enough to be compressed.
+fn server() -> TestServer {
+    let config =
+        AppConfig::from_vars(|key| (key == "HELLO_COMPRESS_MIN_BYTES").then(|| "64".into()))
+            .unwrap();
+    let mut server = TestServer::from_config(config);
+    for name in ["ada", "bob", "cy", "dee", "eve", "fay", "gus", "hal"] {
+        server.get(&format!("/?name={name}"));
+    }
+    server
+}
+
+fn leaderboard(server: &mut TestServer, accept_encoding: &str) -> TestResponse {
+    server.send(
+        Request::get("/leaderboard")
+            .with_header("accept", "application/json")
+            .with_header("accept-encoding", accept_encoding),
+    )
+}
+
+#[test]
+fn prefers_brotli() {
+    let mut server = server();
+    let plain = leaderboard(&mut server, "identity");
+    assert_eq!(plain.header("content-encoding"), None);
+
+    let response = leaderboard(&mut server, "gzip, deflate, br");
+    assert_eq!(response.header("content-encoding"), Some("br"));
+    assert!(response.header("vary").unwrap().contains("accept-encoding"));
+    let mut decoded = Vec::new();
+    brotli::Decompressor::new(response.bytes(), 4096)
+        .read_to_end(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, plain.bytes());
+}
+
+#[test]
+fn falls_back_to_gzip() {
+    let mut server = server();
+    let plain = leaderboard(&mut server, "identity");
+
+    let response = leaderboard(&mut server, "br;q=0.5, gzip");
+    assert_eq!(response.header("content-encoding"), Some("gzip"));
+    let mut decoded = Vec::new();
+    flate2::read::GzDecoder::new(response.bytes())
+        .read_to_end(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, plain.bytes());
+
+    let response = leaderboard(&mut server, "br;q=0, *");
+    assert_eq!(response.header("content-encoding"), Some("gzip"));
+}
+
+#[test]
+fn varies_even_when_uncompressed() {
+    let mut server = server();
+    let response = leaderboard(&mut server, "identity");
+    assert_eq!(response.header("content-encoding"), None);
+    assert_eq!(response.header("vary"), Some("accept, accept-encoding"));
+}
+
+#[test]
+fn skips_small_responses() {
+    let mut server = TestServer::new();
+    let response = server.send(Request::get("/").with_header("accept-encoding", "br, gzip"));
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.header("content-encoding"), None);
+    assert!(response.text().contains("hello world"));
+}
+
+#[test]
+fn skips_other_content_types() {
+    let compression = Compression::default().with_min_size(0);
+    let response = compression.compress(
+        Some("gzip"),
+        Response::ok_body(vec![0u8; 4096], "application/octet-stream"),
+    );
+    assert_eq!(
+        response.response_parts().get_header("content-encoding"),
+        None
+    );
+    assert_eq!(response.response_parts().get_header("vary"), None);
+
+    let response = compression.compress(
+        Some("gzip"),
+        Response::ok_body("a".repeat(4096), "text/plain; charset=utf-8"),
+    );
+    assert_eq!(
+        response.response_parts().get_header("content-encoding"),
+        Some("gzip")
+    );
+}
+
+#[test]
+fn parses_accept_encoding() {
+    use ContentEncoding::*;
+    assert_eq!(accepted_encodings(None), vec![]);
+    assert_eq!(accepted_encodings(Some("gzip, br")), vec![Brotli, Gzip]);
+    assert_eq!(
+        accepted_encodings(Some("br;q=0.1, gzip")),
+        vec![Gzip, Brotli]
+    );
+    assert_eq!(accepted_encodings(Some("*;q=0")), vec![]);
+    assert_eq!(accepted_encodings(Some("identity")), vec![]);
+}
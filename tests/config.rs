@@ -0,0 +1,151 @@
+//! [`AppConfig::from_vars`], with each kind of value parsed and rejected
+//! under the name of its variable.
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+use std::path::Path;
+
+fn config(vars: &[(&str, &str)]) -> Result<AppConfig, ConfigError> {
+    AppConfig::from_vars(|key| {
+        vars.iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value.to_string())
+    })
+}
+
+/// Assert the single variable is rejected, naming it in the error.
+fn rejects(key: &str, value: &str) -> ConfigError {
+    let err = config(&[(key, value)]).unwrap_err();
+    assert_eq!(err.key, key, "{err}");
+    assert_eq!(err.value, value);
+    assert!(
+        err.to_string()
+            .starts_with(&format!("invalid value for {key}: "))
+    );
+    err
+}
+
+#[test]
+fn defaults_without_variables() {
+    let config = config(&[]).unwrap();
+    assert_eq!(config.server.host(), [0, 0, 0, 0]);
+    assert_eq!(config.server.port(), 8337);
+    assert!(config.server.persist());
+    assert_eq!(config.store.path(), Path::new("state.json"));
+    assert_eq!(config.store.flush_interval(), Duration::from_secs(5));
+    assert_eq!(config.shutdown.drain_timeout(), Duration::from_secs(10));
+    assert_eq!(config.live.max_clients(), 100);
+    assert_eq!(config.events.capacity(), 100);
+    assert!(config.server.admin().is_none());
+}
+
+#[test]
+fn parses_booleans() {
+    for value in ["1", "true", " Yes ", "ON"] {
+        assert!(
+            config(&[("HELLO_PERSIST", value)])
+                .unwrap()
+                .server
+                .persist()
+        );
+    }
+    for value in ["0", "false", "no", "Off"] {
+        assert!(
+            !config(&[("HELLO_PERSIST", value)])
+                .unwrap()
+                .server
+                .persist()
+        );
+    }
+    let err = rejects("HELLO_PERSIST", "maybe");
+    assert_eq!(err.reason, "expected true or false");
+    rejects("HELLO_METRICS", "");
+    rejects("HELLO_LIVE", "2");
+}
+
+#[test]
+fn parses_durations() {
+    let config = config(&[
+        ("HELLO_FLUSH_INTERVAL_SECS", " 30 "),
+        ("HELLO_DRAIN_TIMEOUT_SECS", "0"),
+    ])
+    .unwrap();
+    assert_eq!(config.store.flush_interval(), Duration::from_secs(30));
+    assert_eq!(config.shutdown.drain_timeout(), Duration::ZERO);
+
+    rejects("HELLO_FLUSH_INTERVAL_SECS", "5s");
+    rejects("HELLO_DRAIN_TIMEOUT_SECS", "-1");
+    rejects("HELLO_LIVE_HEARTBEAT_SECS", "1.5");
+}
+
+#[test]
+fn parses_rates() {
+    let config = config(&[("HELLO_RATE_LIMIT", "off")]).unwrap();
+    assert_eq!(config.rate_limit.limit(GREETING_ROUTES[0]), None);
+
+    let err = rejects("HELLO_RATE_LIMIT", "30 per minute");
+    assert!(err.reason.starts_with("expected <requests>/"), "{err}");
+    rejects("HELLO_RATE_LIMIT", "0/m");
+    rejects("HELLO_TRUSTED_PROXIES", "10.0.0.0/8,proxy");
+}
+
+#[test]
+fn parses_addresses() {
+    let config = config(&[("HELLO_HOST", "127.0.0.1"), ("HELLO_PORT", "80")]).unwrap();
+    assert_eq!(config.server.host(), [127, 0, 0, 1]);
+    assert_eq!(config.server.port(), 80);
+
+    rejects("HELLO_HOST", "localhost");
+    rejects("HELLO_HOST", "::1");
+    rejects("HELLO_PORT", "65536");
+    rejects("HELLO_HTTP_REDIRECT_PORT", "80");
+}
+
+#[test]
+fn parses_paths() {
+    let config = config(&[("HELLO_STATE_PATH", "/srv/hello/state.json")]).unwrap();
+    assert_eq!(config.store.path(), Path::new("/srv/hello/state.json"));
+
+    for key in ["HELLO_STATE_PATH", "HELLO_PUBLIC_DIR", "HELLO_LOCALES_DIR"] {
+        assert_eq!(rejects(key, " ").reason, "must not be empty");
+    }
+    rejects("HELLO_ADMIN_TOKEN_FILE", "/nonexistent/token");
+    rejects("HELLO_TLS_CERT", "cert.pem");
+}
+
+#[test]
+fn paths_default_to_the_working_directory() {
+    // rather than following the state file
+    let config = config(&[
+        ("HELLO_STATE_PATH", "/srv/hello/state.json"),
+        ("HELLO_ADMIN_TOKEN", "0123456789abcdef0123456789abcdef"),
+        ("HELLO_ACME_DOMAINS", "hello.example.com"),
+    ])
+    .unwrap();
+    #[cfg(not(feature = "embed-public"))]
+    assert_eq!(
+        config.server.static_files().unwrap().dir(),
+        Some(Path::new("public"))
+    );
+    assert_eq!(config.server.acme().unwrap().dir(), Path::new("acme"));
+    assert_eq!(
+        config.server.admin().unwrap().audit_log(),
+        Path::new("audit.log")
+    );
+}
+
+#[test]
+fn rejects_zero_sizes() {
+    for key in [
+        "HELLO_FLUSH_INTERVAL_SECS",
+        "HELLO_MAX_NAMES",
+        "HELLO_LIVE_MAX_CLIENTS",
+        "HELLO_LIVE_HEARTBEAT_SECS",
+        "HELLO_EVENTS_BUFFER",
+    ] {
+        assert_eq!(rejects(key, "0").reason, "must be at least 1");
+        assert_eq!(rejects(key, " 0 ").reason, "must be at least 1");
+    }
+    // compressing every response is still a choice
+    let config = config(&[("HELLO_COMPRESS_MIN_BYTES", "0")]).unwrap();
+    assert_eq!(config.compression.min_size(), 0);
+}
@@ -0,0 +1,149 @@
+//! The `/events` visit stream, resumed through [`TestServer`] and followed
+//! over a real connection until the server shuts down.
+use beet::exports::futures_lite::StreamExt;
+use beet::exports::futures_lite::future;
+use beet::prelude::*;
+use bytes::Bytes;
+use hello_lightsail::prelude::*;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::pin::pin;
+
+/// The start of the stream for a client reconnecting with `Last-Event-ID`.
+fn resume(server: &mut TestServer, last_event_id: &str) -> String {
+    let response =
+        server.send(Request::get(EVENTS_ROUTE).with_header("last-event-id", last_event_id));
+    assert_eq!(response.status(), 200);
+    let after = response.header(EVENT_STREAM_MARKER).unwrap();
+    let events = server.app_mut().world().resource::<VisitEvents>().clone();
+    let stream = events.stream(
+        hello_lightsail::prelude::last_event_id(Some(after)),
+        Connections::default(),
+    );
+    let start = future::block_on(pin!(stream).next()).unwrap();
+    String::from_utf8(start.to_vec()).unwrap()
+}
+
+#[test]
+fn replays_missed_visits() {
+    let mut server = TestServer::new();
+    let response = server.get(EVENTS_ROUTE);
+    assert_eq!(response.header("content-type"), Some(EVENT_STREAM_TYPE));
+    assert_eq!(response.header("cache-control"), Some("no-store"));
+    // the listener streams the body
+    assert_eq!(response.header(EVENT_STREAM_MARKER), Some(""));
+    assert_eq!(response.text(), "");
+
+    server.get("/?name=pete");
+    server.get("/?name=%3Cb%3E");
+    server.get("/");
+    server.get("/hello/zoe");
+
+    // rejected names are not visits
+    assert_eq!(
+        resume(&mut server, "1"),
+        "retry: 3000\n\n\
+         id: 2\nevent: visit\ndata: {\"count\":2}\n\n\
+         id: 3\nevent: visit\ndata: {\"count\":3,\"name\":\"zoe\"}\n\n"
+    );
+    assert_eq!(resume(&mut server, "3"), "retry: 3000\n\n");
+    // from before a restart, or not ours at all
+    assert_eq!(resume(&mut server, "42").matches("event: visit").count(), 3);
+    assert_eq!(resume(&mut server, "abc"), "retry: 3000\n\n");
+}
+
+#[test]
+fn buffers_recent_visits() {
+    let config = AppConfig::from_vars(|key| (key == "HELLO_EVENTS_BUFFER").then(|| "2".into()));
+    let mut server = TestServer::from_config(config.unwrap());
+    for _ in 0..3 {
+        server.get("/");
+    }
+    let replay = resume(&mut server, "0");
+    assert!(!replay.contains("id: 1\n"), "{replay}");
+    assert!(replay.contains("id: 2\n") && replay.contains("id: 3\n"));
+
+    let config = AppConfig::from_vars(|key| (key == "HELLO_EVENTS").then(|| "false".into()));
+    let mut server = TestServer::from_config(config.unwrap());
+    assert_eq!(server.get(EVENTS_ROUTE).status(), 404);
+}
+
+#[test]
+fn limits_clients() {
+    let config =
+        AppConfig::from_vars(|key| (key == "HELLO_EVENTS_MAX_CLIENTS").then(|| "1".into()));
+    let mut server = TestServer::from_config(config.unwrap());
+    let events = server.app_mut().world().resource::<VisitEvents>().clone();
+    assert_eq!(server.get(EVENTS_ROUTE).status(), 200);
+
+    let first = events.stream(None, Connections::default());
+    assert_eq!(events.active(), 1);
+    let response = server.get(EVENTS_ROUTE);
+    assert_eq!(response.status(), 503);
+    assert_eq!(response.header("retry-after"), Some("30"));
+    assert_eq!(response.header(EVENT_STREAM_MARKER), None);
+
+    // one that joined since the handler checked only gets the retry
+    let late: Vec<Bytes> = future::block_on(events.stream(None, Connections::default()).collect());
+    assert_eq!(late, vec![Bytes::from("retry: 3000\n\n")]);
+    assert_eq!(events.active(), 1);
+
+    // a disconnected client frees its slot
+    drop(first);
+    assert_eq!(events.active(), 0);
+    assert_eq!(server.get(EVENTS_ROUTE).status(), 200);
+
+    let err = AppConfig::from_vars(|key| (key == "HELLO_EVENTS_MAX_CLIENTS").then(|| "0".into()))
+        .unwrap_err();
+    assert_eq!(err.key, "HELLO_EVENTS_MAX_CLIENTS");
+}
+
+#[test]
+fn streams_visits_until_shutdown() {
+    let config = AppConfig::from_vars(|key| (key == "HELLO_STATIC_FILES").then(|| "false".into()));
+    let server = TestServer::listen(config.unwrap());
+
+    get(&server, "/?name=pete");
+    let mut events = server.connect();
+    events
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\nLast-Event-ID: 0\r\n\r\n")
+        .unwrap();
+    let mut received = String::new();
+    read_until(
+        &mut events,
+        &mut received,
+        "{\"count\":1,\"name\":\"pete\"}",
+    );
+    assert!(received.starts_with("HTTP/1.1 200"), "{received}");
+
+    get(&server, "/hello/zoe");
+    read_until(&mut events, &mut received, "id: 2\nevent: visit\n");
+    read_until(&mut events, &mut received, "{\"count\":2,\"name\":\"zoe\"}");
+
+    // the stream ends, so the server has nothing left to drain
+    server.shutdown();
+    events.read_to_string(&mut received).unwrap();
+    assert_eq!(server.join(), AppExit::Success);
+}
+
+/// Greet over a connection of its own.
+fn get(server: &ListeningServer, path: &str) {
+    let mut stream = server.connect();
+    write!(
+        stream,
+        "GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    )
+    .unwrap();
+    stream.read_to_end(&mut Vec::new()).unwrap();
+}
+
+/// Read the chunked stream until it contains the text.
+fn read_until(stream: &mut TcpStream, received: &mut String, text: &str) {
+    let mut buf = [0; 1024];
+    while !received.contains(text) {
+        let len = stream.read(&mut buf).unwrap();
+        assert!(len > 0, "the stream ended before {text:?}: {received}");
+        received.push_str(std::str::from_utf8(&buf[..len]).unwrap());
+    }
+}
@@ -0,0 +1,140 @@
+//! Visits counted per normalized name in the [`NameCounts`], bounded by an
+//! LRU, and the top names served at `/leaderboard`.
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+use serde_json::Value;
+use serde_json::json;
+
+fn counts(names: &[(&str, u32)]) -> NameCounts {
+    let mut counts = NameCounts::new(10);
+    counts.restore(names.iter().map(|(name, count)| (name.to_string(), *count)));
+    counts
+}
+
+#[test]
+fn counts_visits_per_name() {
+    let mut counts = NameCounts::new(10);
+    assert!(counts.is_empty());
+    assert_eq!(counts.visit("pete"), 1);
+    assert_eq!(counts.visit("pete"), 2);
+    assert_eq!(counts.visit("zoe"), 1);
+    assert_eq!(counts.get("pete"), Some(2));
+    assert_eq!(counts.get("nobody"), None);
+    assert_eq!(counts.len(), 2);
+
+    // at least one name is tracked
+    assert_eq!(NameCounts::new(0).capacity(), 1);
+}
+
+#[test]
+fn evicts_the_least_recently_visited() {
+    let mut counts = NameCounts::new(2);
+    counts.visit("pete");
+    counts.visit("zoe");
+    counts.visit("pete");
+    counts.visit("ann");
+    assert_eq!(counts.len(), 2);
+    assert_eq!(counts.get("zoe"), None);
+    assert_eq!(counts.get("pete"), Some(2));
+    // an evicted name starts over
+    assert_eq!(counts.visit("zoe"), 1);
+    assert_eq!(counts.get("pete"), None);
+}
+
+#[test]
+fn ranks_by_visits_then_name() {
+    let counts = counts(&[("zoe", 3), ("ann", 1), ("pete", 3), ("bob", 5)]);
+    assert_eq!(
+        counts.top(3),
+        vec![("bob".into(), 5), ("pete".into(), 3), ("zoe".into(), 3)]
+    );
+    assert_eq!(counts.top(10).len(), 4);
+    assert!(counts.top(0).is_empty());
+}
+
+#[test]
+fn restores_the_most_recent_names() {
+    let mut counts = NameCounts::new(2);
+    counts.restore([("ann".into(), 1), ("bob".into(), 2), ("zoe".into(), 3)]);
+    assert_eq!(counts.to_vec(), vec![("bob".into(), 2), ("zoe".into(), 3)]);
+
+    // least recently visited first, as persisted
+    counts.visit("bob");
+    assert_eq!(counts.to_vec(), vec![("zoe".into(), 3), ("bob".into(), 3)]);
+}
+
+#[test]
+fn greets_returning_names() {
+    let mut server = TestServer::new();
+    let greet = |server: &mut TestServer, name: &str| {
+        server.get(&format!("/?name={name}&format=json")).json()
+    };
+    assert_eq!(greet(&mut server, "Pete")["visits"], 1);
+    // the same name once case-folded and normalized
+    assert_eq!(greet(&mut server, "PETE")["visits"], 2);
+    let visit = greet(&mut server, "pete");
+    assert_eq!(visit["visits"], 3);
+    assert_eq!(visit["visitor_number"], 3);
+    assert_eq!(greet(&mut server, "Jos%C3%A9")["visits"], 1);
+    assert_eq!(greet(&mut server, "JOSE%CC%81")["visits"], 2);
+
+    let text = server.get("/?name=pete").text().to_string();
+    assert!(
+        text.contains("welcome back, pete, that makes 4 visits"),
+        "{text}"
+    );
+    // no name, no visits
+    assert_eq!(server.get("/?format=json").json()["visits"], Value::Null);
+}
+
+#[test]
+fn serves_the_leaderboard() {
+    let mut server = TestServer::new();
+    for name in ["pete", "zoe", "Pete", "ann", "zoe", "PETE"] {
+        server.get(&format!("/?name={name}"));
+    }
+
+    let response = server.get("/leaderboard");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.header("content-type"), Some("application/json"));
+    assert_eq!(
+        response.json(),
+        json!({ "leaders": [
+            { "name": "pete", "visits": 3 },
+            { "name": "zoe", "visits": 2 },
+            { "name": "ann", "visits": 1 },
+        ]})
+    );
+
+    let response = server.get("/leaderboard?limit=2&format=text");
+    assert_eq!(response.text(), "1. pete (3 visits)\n2. zoe (2 visits)\n");
+    assert_eq!(
+        server.get("/leaderboard?limit=nope").json()["leaders"]
+            .as_array()
+            .unwrap()
+            .len(),
+        3
+    );
+
+    let response = server.send(Request::get("/leaderboard").with_header("accept", "text/html"));
+    assert_eq!(response.status(), 406);
+}
+
+#[test]
+fn bounds_the_names_tracked() {
+    let config =
+        AppConfig::from_vars(|key| (key == "HELLO_MAX_NAMES").then(|| "2".into())).unwrap();
+    let mut server = TestServer::from_config(config);
+    for name in ["pete", "pete", "zoe", "ann"] {
+        server.get(&format!("/?name={name}"));
+    }
+    assert_eq!(
+        server.get("/leaderboard").json(),
+        json!({ "leaders": [
+            { "name": "ann", "visits": 1 },
+            { "name": "zoe", "visits": 1 },
+        ]})
+    );
+    // every visit still counts towards the total
+    assert_eq!(server.count(), 4);
+}
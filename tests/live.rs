@@ -0,0 +1,114 @@
+//! The live count WebSocket, from the handshake through [`TestServer`] to a
+//! real client that sees the count change and the server shut down.
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use tungstenite::Message;
+use tungstenite::protocol::frame::coding::CloseCode;
+
+/// The handshake from RFC 6455.
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+fn handshake(version: &str, key: &str) -> Request {
+    Request::get(LIVE_COUNT_ROUTE)
+        .with_header("upgrade", "websocket")
+        .with_header("connection", "keep-alive, Upgrade")
+        .with_header("sec-websocket-version", version)
+        .with_header("sec-websocket-key", key)
+}
+
+#[test]
+fn validates_the_handshake() {
+    let mut server = TestServer::new();
+
+    let response = server.get(LIVE_COUNT_ROUTE);
+    assert_eq!(response.status(), 426);
+    assert_eq!(response.header("upgrade"), Some("websocket"));
+
+    let response = server.send(handshake("8", KEY));
+    assert_eq!(response.status(), 426);
+    assert_eq!(response.header("sec-websocket-version"), Some("13"));
+
+    let response = server.send(handshake("13", "c2hvcnQ="));
+    assert_eq!(response.status(), 400);
+
+    let response = server.send(handshake("13", KEY));
+    assert_eq!(response.status(), 101);
+    assert_eq!(
+        response.header("sec-websocket-accept"),
+        Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+    );
+}
+
+#[test]
+fn limits_clients() {
+    let mut config = AppConfig::from_vars(|_| None).unwrap();
+    config.live = config.live.with_max_clients(0);
+    let mut server = TestServer::from_config(config);
+    let response = server.send(handshake("13", KEY));
+    assert_eq!(response.status(), 503);
+    assert_eq!(response.header("retry-after"), Some("30"));
+
+    let config = AppConfig::from_vars(|key| (key == "HELLO_LIVE").then(|| "false".into()));
+    let mut server = TestServer::from_config(config.unwrap());
+    assert_eq!(server.send(handshake("13", KEY)).status(), 404);
+
+    let err = AppConfig::from_vars(|key| (key == "HELLO_LIVE_HEARTBEAT_SECS").then(|| "0".into()))
+        .unwrap_err();
+    assert_eq!(err.key, "HELLO_LIVE_HEARTBEAT_SECS");
+}
+
+#[test]
+fn pushes_the_count_until_shutdown() {
+    let config = AppConfig::from_vars(|key| match key {
+        "HELLO_STATIC_FILES" => Some("false".into()),
+        "HELLO_LIVE_HEARTBEAT_SECS" => Some("1".into()),
+        _ => None,
+    });
+    let server = TestServer::listen(config.unwrap());
+
+    let url = format!("ws://{}{LIVE_COUNT_ROUTE}", server.addr());
+    let (mut socket, response) = tungstenite::client(url, server.connect()).unwrap();
+    assert_eq!(response.status(), 101);
+    assert_eq!(read_json(&mut socket), serde_json::json!({ "count": 0 }));
+
+    // pinged every second, and the pong keeps the connection open
+    let start = Instant::now();
+    while !matches!(socket.read().unwrap(), Message::Ping(_)) {}
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    let mut stream = server.connect();
+    stream
+        .write_all(b"GET /?name=pete HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    stream.read_to_end(&mut Vec::new()).unwrap();
+    assert_eq!(
+        read_json(&mut socket),
+        serde_json::json!({ "count": 1, "name": "pete" })
+    );
+
+    server.shutdown();
+    let frame = loop {
+        match socket.read().unwrap() {
+            Message::Close(frame) => break frame.unwrap(),
+            _ => continue,
+        }
+    };
+    assert_eq!(frame.code, CloseCode::Away);
+    // the close is answered, after which the server has nothing left to drain
+    while socket.read().is_ok() {}
+    assert_eq!(server.join(), AppExit::Success);
+}
+
+/// The next text message, skipping pings.
+fn read_json(socket: &mut tungstenite::WebSocket<TcpStream>) -> serde_json::Value {
+    loop {
+        match socket.read().unwrap() {
+            Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+            Message::Ping(_) | Message::Pong(_) => continue,
+            message => panic!("unexpected message {message:?}"),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Localized greetings, exercised through the [`TestServer`].
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+
+fn greeting(locale: &str, visitor_number: u32, visits: Option<u32>) -> GreetingText {
+    Greeting {
+        name: "pete".into(),
+        visitor_number,
+        visits,
+        locale: locale.parse().unwrap(),
+    }
+    .localize(&Catalogs::embedded())
+}
+
+#[test]
+fn negotiates_the_locale() {
+    let mut server = TestServer::new();
+    let mut greet = |accept_language: &str| {
+        server.send(Request::get("/").with_header("accept-language", accept_language))
+    };
+
+    let response = greet("de-AT, de;q=0.9, en;q=0.5");
+    assert!(
+        response.text().contains("hallo Welt"),
+        "{}",
+        response.text()
+    );
+    assert_eq!(response.header("content-language"), Some("de"));
+    assert_eq!(response.header("vary"), Some("accept, accept-language"));
+
+    // by quality, not order
+    let response = greet("fr;q=0.5, es");
+    assert_eq!(response.header("content-language"), Some("es"));
+    assert!(
+        response.text().contains("hola mundo"),
+        "{}",
+        response.text()
+    );
+
+    for accept_language in ["ja", "*", "fr;q=0", "not a locale"] {
+        let response = greet(accept_language);
+        assert_eq!(response.header("content-language"), Some("en"));
+        assert!(
+            response.text().contains("hello world"),
+            "{}",
+            response.text()
+        );
+    }
+
+    // the parameter overrides the header
+    let response =
+        server.send(Request::get("/?lang=fr&name=pete").with_header("accept-language", "de"));
+    assert!(
+        response.text().contains("bonjour pete"),
+        "{}",
+        response.text()
+    );
+    assert_eq!(
+        server.get("/?lang=xx").header("content-language"),
+        Some("en")
+    );
+}
+
+#[test]
+fn formats_ordinals_per_locale() {
+    for (number, ordinal) in [
+        (1, "1st"),
+        (2, "2nd"),
+        (3, "3rd"),
+        (4, "4th"),
+        (11, "11th"),
+        (12, "12th"),
+        (13, "13th"),
+        (21, "21st"),
+        (102, "102nd"),
+        (111, "111th"),
+    ] {
+        assert_eq!(
+            greeting("en", number, None).visitor,
+            format!("you are the {ordinal} visitor")
+        );
+    }
+    assert_eq!(greeting("fr", 1, None).visitor, "vous êtes le 1er visiteur");
+    assert_eq!(greeting("fr", 2, None).visitor, "vous êtes le 2e visiteur");
+    assert_eq!(greeting("de", 3, None).visitor, "du bist der 3. Besucher");
+}
+
+#[test]
+fn pluralizes_visits() {
+    assert_eq!(greeting("en", 1, Some(1)).salutation, "hello pete");
+    assert_eq!(
+        greeting("en", 1, Some(2)).salutation,
+        "welcome back, pete, that makes 2 visits"
+    );
+    assert_eq!(
+        greeting("fr", 1, Some(3)).salutation,
+        "bon retour, pete, cela fait 3 visites"
+    );
+    let hint = greeting("de", 1, None).hint;
+    assert_eq!(
+        format!("{}name{}", hint.0, hint.1),
+        "gib den Parameter name an, um persönlich begrüßt zu werden."
+    );
+}
+
+#[test]
+fn greeting_page_is_localized() {
+    let mut server = TestServer::new();
+    let response =
+        server.send(Request::get("/?name=pete&lang=de-CH").with_header("accept", "text/html"));
+    let html = response.text();
+    assert!(html.contains("<html lang=\"de\">"), "{html}");
+    assert!(html.contains("<h1>hallo pete</h1>"), "{html}");
+    assert!(html.contains("<p>du bist der 1. Besucher</p>"), "{html}");
+    assert!(
+        html.contains("<p>gib den Parameter <code>name</code> an,"),
+        "{html}"
+    );
+
+    // JSON keeps the data, with the locale it would be shown in
+    let response = server.get("/?lang=fr&format=json");
+    assert_eq!(response.json()["name"], "le monde");
+    assert_eq!(response.json()["locale"], "fr");
+}
+
+#[test]
+fn loads_catalogs_from_a_directory() {
+    let dir = std::env::temp_dir().join(format!("hello-locales-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let write = |path: &str, source: &str| {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, source).unwrap();
+    };
+    let config = || {
+        let dir = dir.to_str().unwrap().to_string();
+        AppConfig::from_vars(|key| (key == "HELLO_LOCALES_DIR").then(|| dir.clone()))
+    };
+
+    write("de/greeting.ftl", "hello = moin { $name }\n");
+    let err = config().unwrap_err();
+    assert_eq!(err.key, "HELLO_LOCALES_DIR");
+    assert_eq!(err.reason, "there is no catalog for en");
+
+    write("en/greeting.ftl", "hello = howdy { $name }\n");
+    write("en/README.md", "not a catalog");
+    let mut server = TestServer::from_config(config().unwrap());
+    let response = server.get("/?name=pete&lang=de");
+    // a missing message falls back to English, then to its id
+    assert!(response.text().contains("moin pete"), "{}", response.text());
+    assert!(
+        response.text().contains("visitor-number"),
+        "{}",
+        response.text()
+    );
+    assert!(server.get("/").text().contains("howdy world"));
+
+    write("en/broken.ftl", "hello = { $name\n");
+    assert!(config().unwrap_err().reason.starts_with("en: "));
+}
@@ -0,0 +1,123 @@
+//! Requests recorded in the [`Metrics`] and rendered by `/metrics` in the
+//! Prometheus text format.
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+
+/// The value of the sample with exactly these name and labels.
+fn sample(body: &str, series: &str) -> Option<f64> {
+    body.lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .map(|value| value.parse().unwrap())
+}
+
+#[test]
+fn renders_cumulative_histograms() {
+    let mut metrics = Metrics::default();
+    metrics.observe("GET", "/", 200, Duration::from_micros(300));
+    metrics.observe("GET", "/", 200, Duration::from_millis(20));
+    metrics.observe("GET", "/", 500, Duration::from_secs(2));
+    metrics.throttle("/");
+    let body = metrics.render(7);
+
+    let requests = |status| {
+        sample(
+            &body,
+            &format!("http_requests_total{{method=\"GET\",path=\"/\",status=\"{status}\"}}"),
+        )
+    };
+    assert_eq!(requests(200), Some(2.));
+    assert_eq!(requests(500), Some(1.));
+    let bucket = |le: &str| {
+        sample(
+            &body,
+            &format!(
+                "http_request_duration_seconds_bucket{{method=\"GET\",path=\"/\",le=\"{le}\"}}"
+            ),
+        )
+    };
+    assert_eq!(bucket("0.0005"), Some(1.));
+    assert_eq!(bucket("0.01"), Some(1.));
+    assert_eq!(bucket("0.025"), Some(2.));
+    assert_eq!(bucket("1"), Some(2.));
+    assert_eq!(bucket("+Inf"), Some(3.));
+    assert_eq!(
+        sample(
+            &body,
+            "http_request_duration_seconds_count{method=\"GET\",path=\"/\"}"
+        ),
+        Some(3.)
+    );
+    let sum = sample(
+        &body,
+        "http_request_duration_seconds_sum{method=\"GET\",path=\"/\"}",
+    )
+    .unwrap();
+    assert!((sum - 2.0203).abs() < 1e-9, "{sum}");
+    assert_eq!(
+        sample(&body, "http_requests_throttled_total{path=\"/\"}"),
+        Some(1.)
+    );
+    assert_eq!(sample(&body, "hello_visitor_count"), Some(7.));
+    assert!(body.contains("# TYPE http_request_duration_seconds histogram\n"));
+}
+
+#[test]
+fn escapes_label_values() {
+    let mut metrics = Metrics::default();
+    metrics.observe("GET", "/a\"b\\c\nd", 200, Duration::ZERO);
+    let body = metrics.render(0);
+    assert!(
+        body.contains(r#"http_requests_total{method="GET",path="/a\"b\\c\nd",status="200"} 1"#),
+        "{body}"
+    );
+}
+
+#[test]
+fn serves_request_metrics() {
+    let mut server = TestServer::new();
+    server.get("/");
+    server.get("/?name=pete");
+    server.get("/scanner-probe");
+    server.send(Request::post("/healthz"));
+
+    let response = server.get("/metrics");
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.header("content-type"),
+        Some("text/plain; version=0.0.4; charset=utf-8")
+    );
+    let body = response.text();
+    assert_eq!(
+        sample(
+            body,
+            "http_requests_total{method=\"GET\",path=\"/\",status=\"200\"}"
+        ),
+        Some(2.)
+    );
+    assert_eq!(
+        sample(
+            body,
+            "http_requests_total{method=\"POST\",path=\"/healthz\",status=\"405\"}"
+        ),
+        Some(1.)
+    );
+    // labelled by route rather than the raw path
+    assert!(!body.contains("scanner-probe"), "{body}");
+    assert_eq!(sample(body, "hello_visitor_count"), Some(2.));
+    assert!(sample(body, "process_uptime_seconds").unwrap() >= 0.);
+    #[cfg(target_os = "linux")]
+    {
+        assert!(sample(body, "process_resident_memory_bytes").unwrap() > 0.);
+        assert!(sample(body, "process_cpu_seconds_total").is_some());
+    }
+}
+
+#[test]
+fn can_be_disabled() {
+    let config =
+        AppConfig::from_vars(|key| (key == "HELLO_METRICS").then(|| "false".into())).unwrap();
+    let mut server = TestServer::from_config(config);
+    let response = server.get("/metrics");
+    assert_eq!(response.status(), 404);
+    assert!(!response.text().contains("http_requests_total"));
+}
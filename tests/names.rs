@@ -0,0 +1,221 @@
+//! [`VisitorName`] validation, with property tests over arbitrary Unicode.
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+use percent_encoding::NON_ALPHANUMERIC;
+use percent_encoding::utf8_percent_encode;
+use proptest::prelude::*;
+use proptest::test_runner::Config;
+use proptest::test_runner::TestRunner;
+use unicode_normalization::is_nfc;
+
+#[test]
+fn normalizes_whitespace() {
+    let name = VisitorName::parse("  Jean-Luc \u{3000}  O'Brien ").unwrap();
+    assert_eq!(name.as_str(), "Jean-Luc O'Brien");
+    // composed and decomposed forms are the same name
+    assert_eq!(
+        VisitorName::parse("Zoe\u{0308}"),
+        VisitorName::parse("Zo\u{00eb}")
+    );
+    assert_eq!(VisitorName::parse("ZOË").unwrap().key(), "zoë");
+}
+
+#[test]
+fn rejects_bad_names() {
+    assert_eq!(VisitorName::parse(" \t"), Err(NameError::InvalidChar('\t')));
+    assert_eq!(VisitorName::parse("   "), Err(NameError::Blank));
+    assert_eq!(VisitorName::parse(&"a".repeat(33)), Err(NameError::TooLong));
+    assert!(VisitorName::parse(&"a".repeat(32)).is_ok());
+    assert_eq!(VisitorName::parse("<b>"), Err(NameError::InvalidChar('<')));
+    assert_eq!(
+        VisitorName::parse("pete\u{202e}"),
+        Err(NameError::InvalidChar('\u{202e}'))
+    );
+    // a combining mark needs a letter to combine with
+    assert_eq!(
+        VisitorName::parse("\u{0301}pete"),
+        Err(NameError::InvalidChar('\u{0301}'))
+    );
+    assert_eq!(
+        NameError::InvalidChar('<').to_string(),
+        "the name contains '<' (U+003C), only letters, numbers, spaces and ' - . are allowed"
+    );
+}
+
+#[test]
+fn greeting_rejects_bad_names() {
+    let mut server = TestServer::new();
+
+    let response = server.get("/?name=%3Cscript%3Ealert(1)%3C%2Fscript%3E");
+    assert_eq!(response.status(), 400);
+    assert!(
+        response
+            .text()
+            .starts_with("Bad Request: the name contains '<'"),
+        "{}",
+        response.text()
+    );
+
+    let response =
+        server.send(Request::get("/hello/%3Cscript%3E").with_header("accept", "text/html"));
+    assert_eq!(response.status(), 400);
+    assert!(!response.text().contains("<script>"), "{}", response.text());
+
+    let response = server.get(&format!("/?name={}", "a".repeat(40)));
+    assert_eq!(response.status(), 400);
+
+    // rejected names are not visits, and a blank one greets the world
+    assert_eq!(server.count(), 0);
+    assert!(server.get("/?name=+").text().contains("hello world"));
+    assert_eq!(server.count(), 1);
+}
+
+#[test]
+fn plus_is_a_space_only_in_queries() {
+    assert_eq!(decode_param("a+b%2Bc"), "a b+c");
+    let mut server = TestServer::new();
+    assert!(
+        server
+            .get("/?name=jean+luc")
+            .text()
+            .contains("hello jean luc")
+    );
+    let response = server.get("/hello/jean+luc");
+    assert_eq!(response.status(), 400);
+    assert!(response.text().contains("'+'"), "{}", response.text());
+    // the same visitor, so welcomed back
+    assert!(
+        server
+            .get("/hello/jean%20luc")
+            .text()
+            .contains("welcome back, jean luc")
+    );
+}
+
+#[test]
+fn keys_keep_what_folding_lengthens() {
+    // `ß` folds to `ss`, so a cut after folding would drop the last letter
+    let long = "ß".repeat(MAX_NAME_CHARS - 1);
+    let first = VisitorName::parse(&format!("{long}a")).unwrap();
+    let second = VisitorName::parse(&format!("{long}b")).unwrap();
+    assert_ne!(first.key(), second.key());
+    assert_eq!(first.key(), format!("{}a", "ss".repeat(MAX_NAME_CHARS - 1)));
+}
+
+fn echo_name(_server: &mut EntityWorldMut, request: Request) -> Response {
+    let name = request
+        .get_param("name")
+        .map(decode_param)
+        .unwrap_or_default();
+    Response::ok_body(name, "text/plain")
+}
+
+/// A `+` is a space in a query, but not in a path capture.
+#[test]
+fn plus_is_a_space_only_in_query_params() {
+    let mut config = AppConfig::from_vars(|_| None).unwrap();
+    config.server = config
+        .server
+        .with_route(HttpMethod::Get, "/echo/{name}", echo_name)
+        .with_route(HttpMethod::Get, "/echo", echo_name);
+    let server = std::cell::RefCell::new(TestServer::from_config(config));
+    let mut runner = TestRunner::new(Config {
+        cases: 64,
+        ..Config::default()
+    });
+    runner
+        .run(&"[a-z]{0,4}(\\+[a-z%0-9]{0,4}){1,3}", |name| {
+            let mut server = server.borrow_mut();
+            let from_path = server.get(&format!("/echo/{name}"));
+            let from_query = server.get(&format!("/echo?name={name}"));
+            prop_assert_eq!(from_path.text(), decode_param(&name.replace('+', "%2B")));
+            prop_assert_eq!(from_query.text(), decode_param(&name));
+            Ok(())
+        })
+        .unwrap();
+}
+
+/// Arbitrary strings, half of them close enough to names to often parse.
+fn names() -> impl Strategy<Value = String> {
+    prop_oneof![any::<String>(), "[\\p{L}\\p{M}\\p{N}\\p{Zs}'.-]{1,36}",]
+}
+
+proptest! {
+    #[test]
+    fn parsed_names_are_bounded_and_clean(name in names()) {
+        if let Ok(parsed) = VisitorName::parse(&name) {
+            let parsed = parsed.as_str();
+            prop_assert!((1..=MAX_NAME_CHARS).contains(&parsed.chars().count()));
+            prop_assert!(is_nfc(parsed));
+            prop_assert_eq!(parsed.trim(), parsed);
+            prop_assert!(!parsed.contains("  "));
+            prop_assert!(!parsed.chars().any(|char| char.is_control() || "<>&\"".contains(char)));
+        }
+    }
+
+    #[test]
+    fn parsing_is_idempotent(name in names()) {
+        if let Ok(parsed) = VisitorName::parse(&name) {
+            prop_assert_eq!(VisitorName::parse(parsed.as_str()), Ok(parsed.clone()));
+        }
+    }
+
+    #[test]
+    fn errors_do_not_echo_the_name(name in names()) {
+        if let Err(err) = VisitorName::parse(&name) {
+            let message = err.to_string();
+            prop_assert!(message.chars().all(|char| char.is_ascii_graphic() || char == ' '));
+        }
+    }
+
+    #[test]
+    fn keys_differ_when_folded_names_differ(
+        prefix in "[ßﬁa-zA-Zà-öø-ÿ]{0,31}",
+        (first, second) in ("[a-z]", "[a-z]"),
+    ) {
+        let key = |last: &str| VisitorName::parse(&format!("{prefix}{last}")).unwrap().key();
+        if first != second {
+            prop_assert_ne!(key(&first), key(&second));
+        }
+    }
+
+    #[test]
+    fn letters_and_numbers_are_accepted(name in "[\\p{L}\\p{N}][\\p{L}\\p{N} ]{0,9}") {
+        prop_assert!(VisitorName::parse(&name).is_ok(), "{:?}", VisitorName::parse(&name));
+    }
+}
+
+/// Every name is either greeted, escaped, or rejected with a `400`.
+#[test]
+fn greeting_page_never_reflects_markup() {
+    let server = std::cell::RefCell::new(TestServer::new());
+    // each HTML page builds an app, so keep the cases few
+    let mut runner = TestRunner::new(Config {
+        cases: 32,
+        ..Config::default()
+    });
+    runner
+        .run(&names(), |name| {
+            let path = format!("/?name={}", utf8_percent_encode(&name, NON_ALPHANUMERIC));
+            let response = server
+                .borrow_mut()
+                .send(Request::get(&path).with_header("accept", "text/html"));
+            let html = response.text();
+            match VisitorName::parse(&name) {
+                Ok(parsed) => {
+                    prop_assert_eq!(response.status(), 200);
+                    let heading = format!("<h1>hello {}</h1>", EscapeHtml::escape(parsed.as_str()));
+                    let returning = "<h1>welcome back, ";
+                    prop_assert!(
+                        html.contains(&heading) || html.contains(returning),
+                        "{}",
+                        html
+                    );
+                }
+                Err(NameError::Blank) => prop_assert_eq!(response.status(), 200),
+                Err(_) => prop_assert_eq!(response.status(), 400),
+            }
+            Ok(())
+        })
+        .unwrap();
+}
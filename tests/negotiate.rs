@@ -0,0 +1,136 @@
+//! Content negotiation of the greeting with the `Accept` header and the
+//! `?format=` parameter.
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+
+const SUPPORTED: [MediaType; 3] = [MediaType::Text, MediaType::Json, MediaType::Html];
+
+fn negotiate_accept(accept: &str) -> Option<MediaType> {
+    negotiate(&Request::get("/").with_header("accept", accept), &SUPPORTED)
+}
+
+#[test]
+fn honors_quality_values() {
+    assert_eq!(
+        negotiate_accept("text/plain;q=0.5, application/json"),
+        Some(MediaType::Json)
+    );
+    assert_eq!(
+        negotiate_accept("text/plain; q=0.2, text/html ;q=0.9, application/json;q=0.4"),
+        Some(MediaType::Html)
+    );
+    // q=0 means not acceptable
+    assert_eq!(
+        negotiate_accept("application/json;q=0, text/html;q=0.1"),
+        Some(MediaType::Html)
+    );
+    assert_eq!(negotiate_accept("text/plain;q=0"), None);
+    // unparseable values count as 1 and larger ones are clamped
+    assert_eq!(
+        negotiate_accept("text/plain;q=0.9, application/json;q=high"),
+        Some(MediaType::Json)
+    );
+    assert_eq!(
+        negotiate_accept("text/plain;q=7, application/json;q=0.9"),
+        Some(MediaType::Text)
+    );
+    // ties go to the server's preference
+    assert_eq!(
+        negotiate_accept("text/html, application/json"),
+        Some(MediaType::Json)
+    );
+    assert_eq!(negotiate_accept("APPLICATION/Json"), Some(MediaType::Json));
+}
+
+#[test]
+fn matches_wildcards_by_specificity() {
+    assert_eq!(negotiate_accept("*/*"), Some(MediaType::Text));
+    assert_eq!(negotiate_accept("text/*"), Some(MediaType::Text));
+    assert_eq!(
+        negotiate_accept("*/*;q=0.1, text/html"),
+        Some(MediaType::Html)
+    );
+    // the most specific range decides, even with a lower quality
+    assert_eq!(
+        negotiate_accept("text/*;q=0.8, text/plain;q=0.1, text/html;q=0, application/json;q=0.5"),
+        Some(MediaType::Json)
+    );
+    assert_eq!(
+        negotiate_accept("*/*, text/plain;q=0, text/html;q=0"),
+        Some(MediaType::Json)
+    );
+    assert_eq!(negotiate_accept("image/*, audio/ogg"), None);
+}
+
+#[test]
+fn format_overrides_the_header() {
+    let request = |path: &str| Request::get(path).with_header("accept", "text/html");
+    assert_eq!(
+        negotiate(&request("/?format=json"), &SUPPORTED),
+        Some(MediaType::Json)
+    );
+    assert_eq!(
+        negotiate(&request("/?format=TXT"), &SUPPORTED),
+        Some(MediaType::Text)
+    );
+    assert_eq!(negotiate(&request("/?format=xml"), &SUPPORTED), None);
+    assert_eq!(
+        negotiate(&request("/?format=html"), &[MediaType::Json]),
+        None
+    );
+    // no preference at all gets the first supported type
+    assert_eq!(
+        negotiate(&Request::get("/"), &[MediaType::Json, MediaType::Text]),
+        Some(MediaType::Json)
+    );
+}
+
+#[test]
+fn greets_in_the_negotiated_type() {
+    let mut server = TestServer::new();
+    let accept = |server: &mut TestServer, accept: &str| {
+        server.send(Request::get("/?name=pete").with_header("accept", accept))
+    };
+
+    let response = server.get("/?name=pete");
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.header("content-type"),
+        Some("text/plain; charset=utf-8")
+    );
+    assert_eq!(response.header("vary"), Some("accept, accept-language"));
+
+    let response = accept(&mut server, "application/json;q=0.9, text/plain;q=0.5");
+    assert_eq!(response.header("content-type"), Some("application/json"));
+    assert_eq!(response.json()["name"], "pete");
+
+    let response = accept(&mut server, "text/*;q=0.5, text/html");
+    assert_eq!(
+        response.header("content-type"),
+        Some("text/html; charset=utf-8")
+    );
+    assert!(response.text().contains("<!DOCTYPE html>"));
+
+    let response = server.get("/?format=json");
+    assert_eq!(response.header("content-type"), Some("application/json"));
+}
+
+#[test]
+fn refuses_unacceptable_types() {
+    let mut server = TestServer::new();
+    for request in [
+        Request::get("/").with_header("accept", "image/png"),
+        Request::get("/").with_header("accept", "text/plain;q=0"),
+        Request::get("/?format=xml").with_header("accept", "*/*"),
+    ] {
+        let response = server.send(request);
+        assert_eq!(response.status(), 406);
+        assert_eq!(
+            response.text(),
+            "Not Acceptable, supported types are: text/plain, application/json, text/html"
+        );
+        assert_eq!(response.header("vary"), Some("accept"));
+    }
+    // refused visits aren't counted
+    assert_eq!(server.count(), 0);
+}
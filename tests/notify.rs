@@ -0,0 +1,61 @@
+//! The server notifies a fake systemd `NOTIFY_SOCKET` of readiness,
+//! status and watchdog pings.
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+use std::os::unix::net::UnixDatagram;
+
+#[test]
+fn notifies_ready_status_and_watchdog() {
+    let dir = std::env::temp_dir().join(format!("hello-notify-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("notify.sock");
+    std::fs::remove_file(&path).ok();
+    let systemd = UnixDatagram::bind(&path).unwrap();
+    systemd.set_nonblocking(true).unwrap();
+
+    let notifier = SystemdNotifier::new(path.to_str().unwrap())
+        .unwrap()
+        .with_watchdog(Duration::from_millis(100));
+    let mut app = App::new();
+    app.insert_resource(notifier)
+        .add_plugins((MinimalPlugins, NotifyPlugin))
+        .init_plugin::<ServerPlugin>();
+    app.world_mut().spawn((
+        HttpListener::new(([127, 0, 0, 1], 0)),
+        Count(7),
+        handler_exchange(|_, _| Response::ok()),
+    ));
+
+    let mut messages = Vec::new();
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(2)
+        && !messages.iter().any(|msg: &String| msg == "WATCHDOG=1")
+    {
+        app.update();
+        let mut buf = [0u8; 256];
+        while let Ok(len) = systemd.recv(&mut buf) {
+            messages.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    std::fs::remove_dir_all(&dir).ok();
+
+    let ready = messages
+        .iter()
+        .find(|msg| msg.starts_with("READY=1"))
+        .expect("no READY=1 sent");
+    assert!(ready.contains("STATUS=Listening on 127.0.0.1:"), "{ready}");
+    assert!(
+        messages.contains(&"STATUS=Greeted 7 visitors".to_string()),
+        "{messages:?}"
+    );
+    assert!(messages.contains(&"WATCHDOG=1".to_string()), "{messages:?}");
+}
+
+#[test]
+fn disabled_outside_systemd() {
+    let notifier = SystemdNotifier::disabled().with_watchdog(Duration::from_secs(1));
+    assert!(!notifier.is_enabled());
+    assert_eq!(notifier.watchdog(), None);
+    notifier.notify("READY=1").unwrap();
+}
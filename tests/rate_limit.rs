@@ -0,0 +1,112 @@
+//! Client addresses behind trusted proxies, their CIDR ranges, and the
+//! rate limits parsed from `HELLO_RATE_LIMIT`.
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+use std::net::IpAddr;
+
+fn ranges(ranges: &[&str]) -> Vec<IpRange> {
+    ranges.iter().map(|range| range.parse().unwrap()).collect()
+}
+
+fn ip(ip: &str) -> IpAddr {
+    ip.parse().unwrap()
+}
+
+fn request(peer: &str, forwarded_for: Option<&str>) -> Request {
+    let request = Request::get("/").with_header(PEER_ADDR_HEADER, peer);
+    match forwarded_for {
+        Some(forwarded_for) => request.with_header("x-forwarded-for", forwarded_for),
+        None => request,
+    }
+}
+
+#[test]
+fn parses_ip_ranges() {
+    let range: IpRange = "10.0.0.0/8".parse().unwrap();
+    assert!(range.contains(ip("10.1.2.3")));
+    assert!(!range.contains(ip("11.0.0.1")));
+    // ipv4-mapped ipv6 is compared as ipv4
+    assert!(range.contains(ip("::ffff:10.9.9.9")));
+    assert!(!range.contains(ip("::1")));
+
+    let single: IpRange = " 192.168.1.7 ".parse().unwrap();
+    assert!(single.contains(ip("192.168.1.7")));
+    assert!(!single.contains(ip("192.168.1.8")));
+
+    let v6: IpRange = "fd00::/8".parse().unwrap();
+    assert!(v6.contains(ip("fd12:3456::1")));
+    assert!(!v6.contains(ip("fe80::1")));
+
+    let everything: IpRange = "0.0.0.0/0".parse().unwrap();
+    assert!(everything.contains(ip("203.0.113.9")));
+    assert!(!everything.contains(ip("2001:db8::1")));
+
+    for invalid in [
+        "10.0.0.0/33",
+        "::/129",
+        "10.0.0/8",
+        "10.0.0.0/x",
+        "",
+        "proxy",
+    ] {
+        assert!(invalid.parse::<IpRange>().is_err(), "{invalid:?}");
+    }
+}
+
+#[test]
+fn reads_forwarded_for_right_to_left() {
+    let trusted = ranges(&["10.0.0.0/8", "192.168.0.1"]);
+
+    // an untrusted peer is the client, whatever it claims
+    let spoofed = request("203.0.113.5:4000", Some("1.1.1.1"));
+    assert_eq!(client_ip(&spoofed, &trusted), Some(ip("203.0.113.5")));
+
+    // the first untrusted hop from the right, not the leftmost
+    let chained = request(
+        "10.0.0.2:4000",
+        Some("6.6.6.6, 198.51.100.7, 192.168.0.1, 10.0.0.3"),
+    );
+    assert_eq!(client_ip(&chained, &trusted), Some(ip("198.51.100.7")));
+
+    // an unparseable hop stops the walk at the last trusted address
+    let garbled = request("10.0.0.2:4000", Some("198.51.100.7, unknown, 10.0.0.3"));
+    assert_eq!(client_ip(&garbled, &trusted), Some(ip("10.0.0.3")));
+
+    // only trusted hops, or no header at all
+    let internal = request("10.0.0.2:4000", Some("10.0.0.3"));
+    assert_eq!(client_ip(&internal, &trusted), Some(ip("10.0.0.3")));
+    let direct = request("10.0.0.2:4000", None);
+    assert_eq!(client_ip(&direct, &trusted), Some(ip("10.0.0.2")));
+    let mapped = request("[::ffff:10.0.0.2]:4000", Some("2001:db8::1"));
+    assert_eq!(client_ip(&mapped, &trusted), Some(ip("2001:db8::1")));
+
+    // nothing is trusted by default
+    assert_eq!(client_ip(&chained, &[]), Some(ip("10.0.0.2")));
+    assert_eq!(client_ip(&Request::get("/"), &trusted), None);
+}
+
+#[test]
+fn parses_rate_limits() {
+    let limit: RateLimit = "30/m".parse().unwrap();
+    assert_eq!(limit.burst(), 30);
+    assert_eq!(limit.refill_interval(), Duration::from_secs(2));
+
+    let limit: RateLimit = "30/m+10".parse().unwrap();
+    assert_eq!(limit, RateLimit::per_minute(30).with_burst(10));
+    assert_eq!(" 5 / s + 2 ".parse::<RateLimit>().unwrap().burst(), 2);
+
+    for invalid in ["0/m", "30", "30/d", "30/m+0", "30/m+", "+10", "30/m+x"] {
+        assert!(invalid.parse::<RateLimit>().is_err(), "{invalid:?}");
+    }
+}
+
+#[test]
+fn default_greeting_limit_matches_its_syntax() {
+    let default = RateLimiter::default().limit(GREETING_ROUTES[0]);
+    assert_eq!(default, Some("30/m+10".parse().unwrap()));
+
+    let config = AppConfig::from_vars(|key| (key == "HELLO_RATE_LIMIT").then(|| "30/m+10".into()));
+    let limiter = config.unwrap().rate_limit;
+    assert_eq!(limiter.limit(GREETING_ROUTES[1]), default);
+    assert_eq!(RateLimiter::default().limit("/healthz"), None);
+}
@@ -0,0 +1,37 @@
+//! Every response carries an `X-Request-Id`, generated unless the client
+//! sent a valid one.
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+
+#[test]
+fn generates_an_id_for_each_response() {
+    let mut server = TestServer::new();
+    let greeting = server.get("/");
+    let not_found = server.get("/nope");
+    assert_eq!(not_found.status(), 404);
+
+    let first = greeting.header(REQUEST_ID_HEADER).expect("no request id");
+    let second = not_found.header(REQUEST_ID_HEADER).expect("no request id");
+    assert_eq!(first.len(), 36, "{first}");
+    assert!(is_valid_request_id(first), "{first}");
+    assert_ne!(first, second);
+}
+
+#[test]
+fn keeps_a_valid_inbound_id() {
+    let mut server = TestServer::new();
+    let response =
+        server.send(Request::get("/healthz").with_header(REQUEST_ID_HEADER, "lb-1234.abc_DEF"));
+    assert_eq!(response.header(REQUEST_ID_HEADER), Some("lb-1234.abc_DEF"));
+}
+
+#[test]
+fn replaces_an_invalid_inbound_id() {
+    let mut server = TestServer::new();
+    for id in ["has space", "new\nline", "quote\"", &"a".repeat(129)] {
+        let response = server.send(Request::get("/healthz").with_header(REQUEST_ID_HEADER, id));
+        let echoed = response.header(REQUEST_ID_HEADER).expect("no request id");
+        assert_ne!(echoed, id);
+        assert!(is_valid_request_id(echoed), "{echoed}");
+    }
+}
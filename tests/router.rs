@@ -0,0 +1,129 @@
+//! Route patterns, their captures and specificity, and the `405` for a
+//! path only known to other methods.
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+
+fn ok(_server: &mut EntityWorldMut, _request: Request) -> Response {
+    Response::ok_body("ok", "text/plain")
+}
+
+fn router() -> Router {
+    Router::default()
+        .with_route(HttpMethod::Get, "/hello/{name}", ok)
+        .with_route(HttpMethod::Get, "/hello/world", ok)
+        .with_route(HttpMethod::Get, "/files/{*path}", ok)
+        .with_route(HttpMethod::Put, "/items/{id}", ok)
+        .with_route(HttpMethod::Delete, "/items/{id}", ok)
+        .with_route(HttpMethod::Get, "/{*path}", ok)
+}
+
+fn path(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The pattern and captures found for the request.
+fn found(method: HttpMethod, request_path: &str) -> (String, Vec<(String, String)>) {
+    match router().lookup(method, &path(request_path)) {
+        RouteLookup::Found {
+            pattern, captures, ..
+        } => (pattern, captures),
+        other => panic!("{method:?} {request_path} was {other:?}"),
+    }
+}
+
+fn capture(name: &str, value: &str) -> Vec<(String, String)> {
+    vec![(name.to_string(), value.to_string())]
+}
+
+#[test]
+fn captures_segments() {
+    assert_eq!(
+        found(HttpMethod::Get, "/hello/pete"),
+        ("/hello/{name}".into(), capture("name", "pete"))
+    );
+    assert_eq!(
+        found(HttpMethod::Get, "/files/css/site.css"),
+        ("/files/{*path}".into(), capture("path", "css/site.css"))
+    );
+    assert_eq!(
+        found(HttpMethod::Get, "/files"),
+        ("/files/{*path}".into(), capture("path", ""))
+    );
+    assert_eq!(
+        found(HttpMethod::Get, "/"),
+        ("/{*path}".into(), capture("path", ""))
+    );
+
+    let pattern = RoutePattern::new("/a/{b}/c");
+    assert_eq!(pattern.matches(&path("/a/x/c")), Some(capture("b", "x")));
+    assert_eq!(pattern.matches(&path("/a/x")), None);
+    assert_eq!(pattern.matches(&path("/a/x/c/d")), None);
+}
+
+#[test]
+fn prefers_the_most_specific_pattern() {
+    assert_eq!(
+        found(HttpMethod::Get, "/hello/world"),
+        ("/hello/world".into(), vec![])
+    );
+    // a capture beats the catch-all, a literal prefix beats a capture
+    assert_eq!(found(HttpMethod::Get, "/hello/zoe").0, "/hello/{name}");
+    assert_eq!(found(HttpMethod::Get, "/hello/zoe/again").0, "/{*path}");
+    assert_eq!(found(HttpMethod::Get, "/files/a").0, "/files/{*path}");
+
+    // equally specific patterns keep the order they were added
+    let router = Router::default()
+        .with_route(HttpMethod::Get, "/{a}", ok)
+        .with_route(HttpMethod::Get, "/{b}", ok);
+    assert_eq!(router.lookup(HttpMethod::Get, &path("/x")).label(), "/{a}");
+}
+
+#[test]
+fn head_is_answered_by_get_routes() {
+    assert_eq!(found(HttpMethod::Head, "/hello/pete").0, "/hello/{name}");
+
+    let mut server = TestServer::new();
+    let response = server.send(Request::get("/healthz").with_method(HttpMethod::Head));
+    assert_eq!(response.status(), 200);
+}
+
+#[test]
+fn lists_the_allowed_methods() {
+    let RouteLookup::MethodNotAllowed { pattern, allow } =
+        router().lookup(HttpMethod::Post, &path("/items/7"))
+    else {
+        panic!("expected a 405");
+    };
+    assert_eq!(pattern, "/items/{id}");
+    assert_eq!(allow, [HttpMethod::Put, HttpMethod::Delete]);
+
+    let RouteLookup::MethodNotAllowed { allow, .. } =
+        router().lookup(HttpMethod::Post, &path("/hello/pete"))
+    else {
+        panic!("expected a 405");
+    };
+    assert_eq!(allow, [HttpMethod::Get, HttpMethod::Head]);
+
+    let mut server = TestServer::new();
+    let response = server.send(Request::post("/healthz"));
+    assert_eq!(response.status(), 405);
+    assert_eq!(response.header("allow"), Some("GET, HEAD"));
+}
+
+#[test]
+fn catch_all_does_not_allow_unknown_paths() {
+    let lookup = router().lookup(HttpMethod::Post, &path("/nope"));
+    assert!(matches!(lookup, RouteLookup::NotFound), "{lookup:?}");
+    assert_eq!(lookup.label(), "unmatched");
+
+    // the static files are served from a catch-all
+    let mut server = TestServer::new();
+    for method in [HttpMethod::Post, HttpMethod::Put, HttpMethod::Delete] {
+        let response = server.send(Request::new(method, "/nope"));
+        assert_eq!(response.status(), 404, "{method:?}");
+        assert_eq!(response.header("allow"), None);
+    }
+}
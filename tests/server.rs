@@ -0,0 +1,107 @@
+//! The default routes, exercised through the in-process [`TestServer`].
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+
+#[test]
+fn unknown_path_is_not_found() {
+    let mut server = TestServer::new();
+    let response = server.get("/nope");
+    assert_eq!(response.status(), 404);
+    assert_eq!(response.text(), "Not Found: /nope");
+    assert_eq!(server.count(), 0);
+}
+
+#[test]
+fn greets_by_name() {
+    let mut server = TestServer::new();
+
+    let response = server.get("/?name=pete");
+    assert_eq!(response.status(), 200);
+    assert!(
+        response.text().contains("hello pete"),
+        "{}",
+        response.text()
+    );
+
+    let response = server.get("/hello/sam");
+    assert!(response.text().contains("hello sam"), "{}", response.text());
+
+    let response = server.get("/");
+    assert!(
+        response.text().contains("hello world"),
+        "{}",
+        response.text()
+    );
+}
+
+#[test]
+fn greeting_increments_count() {
+    let mut server = TestServer::new();
+    assert_eq!(server.count(), 0);
+
+    let response = server.get("/");
+    assert!(
+        response.text().contains("you are the 1st visitor"),
+        "{}",
+        response.text()
+    );
+    let response = server.get("/?name=pete");
+    assert!(
+        response.text().contains("you are the 2nd visitor"),
+        "{}",
+        response.text()
+    );
+    assert_eq!(server.count(), 2);
+
+    // probes don't count as visits
+    assert_eq!(server.get("/healthz").status(), 200);
+    assert_eq!(server.count(), 2);
+}
+
+#[test]
+fn greeting_page_escapes_the_name() {
+    let mut server = TestServer::new();
+    let response = server.send(Request::get("/?name=o%27brien").with_header("accept", "text/html"));
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.header("content-type"),
+        Some("text/html; charset=utf-8")
+    );
+    let html = response.text();
+    assert!(html.starts_with("<!DOCTYPE html>"), "{html}");
+    assert!(html.contains("<h1>hello o&#39;brien</h1>"), "{html}");
+    assert!(html.contains("<title>hello o&#39;brien</title>"), "{html}");
+    assert!(html.contains("<p>you are the 1st visitor</p>"), "{html}");
+    assert!(html.contains("<code>name</code>"), "{html}");
+}
+
+#[test]
+fn not_found_page_shares_the_layout() {
+    let mut server = TestServer::new();
+    let response = server.send(Request::get("/%3Cb%3E").with_header("accept", "text/html"));
+    assert_eq!(response.status(), 404);
+    let html = response.text();
+    assert!(html.starts_with("<!DOCTYPE html>"), "{html}");
+    assert!(html.contains("<title>404 Not Found</title>"), "{html}");
+    assert!(html.contains("<p>/%3Cb%3E</p>"), "{html}");
+
+    let greeting = server.send(Request::get("/").with_header("accept", "text/html"));
+    // the same document up to the title
+    let head = |html: &str| html[..html.find("<title>").unwrap()].to_string();
+    assert_eq!(head(html), head(greeting.text()));
+}
+
+#[test]
+fn ready_without_persistence() {
+    // a file can't be a directory, so the state path is never writable
+    let config = AppConfig::from_vars(|key| match key {
+        "HELLO_PERSIST" => Some("false".into()),
+        "HELLO_STATE_PATH" => Some("/dev/null/state.json".into()),
+        _ => None,
+    })
+    .unwrap();
+    let mut server = TestServer::from_config(config);
+    let response = server.get("/readyz");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.json()["checks"]["storage_writable"], true);
+}
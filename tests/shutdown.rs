@@ -0,0 +1,41 @@
+//! A request that is in flight when shutdown begins is answered before the
+//! app exits.
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+
+#[test]
+fn drains_in_flight_request_on_shutdown() {
+    let server = TestServer::spawn(|app| {
+        app.add_plugins((MinimalPlugins, ShutdownPlugin))
+            .init_plugin::<ServerPlugin>();
+        app.world_mut().spawn((
+            HttpListener::new(([127, 0, 0, 1], 0)),
+            handler_exchange_async(|_, _| async {
+                async_io::Timer::after(Duration::from_millis(500)).await;
+                Response::ok_body("done", "text/plain")
+            }),
+        ));
+    });
+    let addr = server.addr();
+
+    let mut stream = server.connect();
+    stream
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+
+    // the handler is now sleeping
+    std::thread::sleep(Duration::from_millis(100));
+    server.shutdown();
+
+    // the connection is closed after the response instead of kept alive
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.ends_with("done"), "{response}");
+
+    assert_eq!(server.join(), AppExit::Success);
+    assert!(TcpStream::connect(addr).is_err());
+}
@@ -0,0 +1,251 @@
+//! Files served from `HELLO_PUBLIC_DIR`, exercised through the [`TestServer`].
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// A public directory unique to the test, next to a secret it must not leak.
+fn public_dir(test: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("hello-static-{}-{}", test, std::process::id()));
+    std::fs::remove_dir_all(&root).ok();
+    let public = root.join("public");
+    std::fs::create_dir_all(public.join("docs")).unwrap();
+    std::fs::write(root.join("secret.txt"), "secret").unwrap();
+    std::fs::write(public.join(".env"), "HELLO_ADMIN_TOKEN=secret").unwrap();
+    std::fs::write(public.join("robots.txt"), "User-agent: *\n").unwrap();
+    std::fs::write(public.join("docs/index.html"), "<h1>docs</h1>").unwrap();
+    std::fs::write(public.join("app.js"), "console.log('hello world');").unwrap();
+    std::fs::write(public.join("app.js.br"), "not really brotli").unwrap();
+    public
+}
+
+fn server(public: &Path) -> TestServer {
+    let public = public.to_str().unwrap().to_string();
+    let config =
+        AppConfig::from_vars(|key| (key == "HELLO_PUBLIC_DIR").then(|| public.clone())).unwrap();
+    TestServer::from_config(config)
+}
+
+#[test]
+fn serves_files_by_extension() {
+    let mut server = server(&public_dir("serves"));
+
+    let response = server.get("/robots.txt");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text(), "User-agent: *\n");
+    assert_eq!(
+        response.header("content-type"),
+        Some("text/plain; charset=utf-8")
+    );
+    assert_eq!(response.header("accept-ranges"), Some("bytes"));
+    assert!(response.header("last-modified").is_some());
+
+    let response = server.get("/docs");
+    assert_eq!(response.text(), "<h1>docs</h1>");
+    assert_eq!(
+        response.header("content-type"),
+        Some("text/html; charset=utf-8")
+    );
+
+    // the greeting still owns the root
+    assert!(server.get("/").text().contains("hello world"));
+    assert_eq!(server.get("/missing.txt").status(), 404);
+}
+
+#[test]
+fn does_not_escape_the_directory() {
+    let mut server = server(&public_dir("escape"));
+    for path in [
+        "/../secret.txt",
+        "/docs/../../secret.txt",
+        "/%2e%2e/secret.txt",
+        "/..%2Fsecret.txt",
+        "/.env",
+        "/%2eenv",
+    ] {
+        let response = server.get(path);
+        assert_eq!(response.status(), 404, "{path}: {}", response.text());
+    }
+}
+
+#[test]
+fn answers_conditional_requests() {
+    let mut server = server(&public_dir("conditional"));
+    let response = server.get("/robots.txt");
+    let etag = response.header("etag").unwrap().to_string();
+    let last_modified = response.header("last-modified").unwrap().to_string();
+
+    let response = server.send(Request::get("/robots.txt").with_header("if-none-match", &etag));
+    assert_eq!(response.status(), 304);
+    assert_eq!(response.header("etag"), Some(etag.as_str()));
+    assert!(response.bytes().is_empty());
+
+    let response = server.send(
+        Request::get("/robots.txt").with_header("if-none-match", &format!("\"other\", W/{etag}")),
+    );
+    assert_eq!(response.status(), 304);
+
+    let response =
+        server.send(Request::get("/robots.txt").with_header("if-modified-since", &last_modified));
+    assert_eq!(response.status(), 304);
+
+    // If-None-Match takes precedence
+    let response = server.send(
+        Request::get("/robots.txt")
+            .with_header("if-none-match", "\"other\"")
+            .with_header("if-modified-since", &last_modified),
+    );
+    assert_eq!(response.status(), 200);
+
+    let response = server.send(
+        Request::get("/robots.txt")
+            .with_header("if-modified-since", "Thu, 01 Jan 1970 00:00:00 GMT"),
+    );
+    assert_eq!(response.status(), 200);
+}
+
+#[test]
+fn serves_byte_ranges() {
+    let mut server = server(&public_dir("ranges"));
+    let range = |server: &mut TestServer, range: &str| {
+        server.send(Request::get("/app.js").with_header("range", range))
+    };
+
+    let response = range(&mut server, "bytes=0-6");
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.text(), "console");
+    assert_eq!(response.header("content-range"), Some("bytes 0-6/27"));
+
+    let response = range(&mut server, "bytes=-3");
+    assert_eq!(response.text(), "');");
+    assert_eq!(response.header("content-range"), Some("bytes 24-26/27"));
+
+    let response = range(&mut server, "bytes=19-");
+    assert_eq!(response.text(), "world');");
+
+    let response = range(&mut server, "bytes=27-");
+    assert_eq!(response.status(), 416);
+    assert_eq!(response.header("content-range"), Some("bytes */27"));
+
+    // multiple ranges get the whole file
+    let response = range(&mut server, "bytes=0-1, 3-4");
+    assert_eq!(response.status(), 200);
+
+    // a stale If-Range gets the whole file
+    let response = server.send(
+        Request::get("/app.js")
+            .with_header("range", "bytes=0-6")
+            .with_header("if-range", "\"stale\""),
+    );
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text(), "console.log('hello world');");
+}
+
+#[test]
+fn reads_ranges_from_large_files() {
+    let public = public_dir("large");
+    let contents: Vec<u8> = (0..4 << 20).map(|i: u32| (i % 251) as u8).collect();
+    std::fs::write(public.join("large.bin"), &contents).unwrap();
+    let mut server = server(&public);
+
+    let etag = server.get("/large.bin").header("etag").unwrap().to_string();
+    let response = server.send(
+        Request::get("/large.bin")
+            .with_header("range", "bytes=3000000-3000009")
+            .with_header("if-range", &etag),
+    );
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.bytes(), &contents[3000000..=3000009]);
+    assert_eq!(
+        response.header("content-range"),
+        Some(format!("bytes 3000000-3000009/{}", contents.len()).as_str())
+    );
+
+    // the etag follows the file's size and modification time
+    std::fs::write(public.join("large.bin"), &contents[..10]).unwrap();
+    let response = server.send(Request::get("/large.bin").with_header("if-none-match", &etag));
+    assert_eq!(response.status(), 200);
+    assert_ne!(response.header("etag"), Some(etag.as_str()));
+}
+
+#[test]
+fn streams_large_files() {
+    let public = public_dir("stream");
+    let contents: Vec<u8> = (0..3 << 20).map(|i: u32| (i % 251) as u8).collect();
+    std::fs::write(public.join("large.bin"), &contents).unwrap();
+
+    // the handler only marks the response
+    let response = server(&public).get("/large.bin");
+    assert_eq!(response.status(), 200);
+    assert!(response.bytes().is_empty());
+    assert_eq!(
+        response.header("content-length"),
+        Some(contents.len().to_string().as_str())
+    );
+    let marker = response.header(STATIC_FILE_MARKER).unwrap();
+    let stream = StaticStream::from_marker(marker).unwrap();
+    assert_eq!(stream.content_length(), contents.len() as u64);
+    assert_eq!(StaticStream::from_marker("9-1 %2Fa"), None);
+    assert_eq!(StaticStream::from_marker("0-1"), None);
+    // small files are read as before
+    assert_eq!(server(&public).get("/robots.txt").text(), "User-agent: *\n");
+
+    let public = public.to_str().unwrap().to_string();
+    let config = AppConfig::from_vars(|key| (key == "HELLO_PUBLIC_DIR").then(|| public.clone()));
+    let server = TestServer::listen(config.unwrap());
+    let get = |headers: &str| {
+        let mut stream = server.connect();
+        write!(
+            stream,
+            "GET /large.bin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{headers}\r\n"
+        )
+        .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response.windows(4).position(|window| window == b"\r\n\r\n");
+        let body = response.split_off(split.unwrap() + 4);
+        (String::from_utf8(response).unwrap().to_lowercase(), body)
+    };
+
+    let (head, body) = get("");
+    assert!(head.starts_with("http/1.1 200"), "{head}");
+    assert!(
+        head.contains(&format!("content-length: {}\r\n", contents.len())),
+        "{head}"
+    );
+    assert!(!head.contains(STATIC_FILE_MARKER), "{head}");
+    assert!(body == contents, "the streamed body differs");
+
+    let (head, body) = get("Range: bytes=1000-2098151\r\n");
+    assert!(head.starts_with("http/1.1 206"), "{head}");
+    assert!(
+        body == contents[1000..=2098151],
+        "the streamed range differs"
+    );
+
+    server.shutdown();
+    server.join();
+}
+
+#[test]
+fn serves_precompressed_variants() {
+    let mut server = server(&public_dir("precompressed"));
+
+    let response = server.send(Request::get("/app.js").with_header("accept-encoding", "gzip, br"));
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.header("content-encoding"), Some("br"));
+    assert_eq!(response.text(), "not really brotli");
+    assert_eq!(
+        response.header("content-type"),
+        Some("text/javascript; charset=utf-8")
+    );
+    assert!(response.header("vary").unwrap().contains("accept-encoding"));
+    let compressed_etag = response.header("etag").unwrap().to_string();
+
+    let response = server.send(Request::get("/app.js").with_header("accept-encoding", "gzip"));
+    assert_eq!(response.header("content-encoding"), None);
+    assert_eq!(response.text(), "console.log('hello world');");
+    assert_ne!(response.header("etag"), Some(compressed_etag.as_str()));
+}
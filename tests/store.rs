@@ -0,0 +1,106 @@
+//! The [`StateStore`] file, saved atomically and loaded on startup, with
+//! unparseable and unreadable files handled by the [`StorePlugin`].
+use beet::prelude::*;
+use hello_lightsail::prelude::*;
+use std::path::Path;
+use std::path::PathBuf;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hello-store-{}-{}", name, std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    dir
+}
+
+/// Run startup with the state at `path`, returning the app.
+fn start(path: &Path) -> App {
+    let path = path.display().to_string();
+    let config = AppConfig::from_vars(|key| match key {
+        "HELLO_HOST" => Some("127.0.0.1".into()),
+        "HELLO_PORT" => Some("0".into()),
+        "HELLO_STATE_PATH" => Some(path.clone()),
+        "HELLO_STATIC_FILES" => Some("false".into()),
+        "HELLO_ACCESS_LOG" => Some("off".into()),
+        _ => None,
+    })
+    .unwrap();
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, config));
+    app.update();
+    app
+}
+
+fn count(app: &mut App) -> u32 {
+    app.world_mut()
+        .query::<&Count>()
+        .single(app.world())
+        .unwrap()
+        .0
+}
+
+#[test]
+fn saves_and_loads_state() {
+    let dir = temp_dir("roundtrip");
+    let store = StateStore::default().with_path(dir.join("nested/state.json"));
+    assert_eq!(store.load().unwrap(), PersistedState::default());
+
+    let state = PersistedState {
+        count: 42,
+        names: vec![("pete".into(), 3), ("zoe".into(), 1)],
+    };
+    store.save(&state).unwrap();
+    assert_eq!(store.load().unwrap(), state);
+    // written beside the file and renamed over it
+    assert!(!dir.join("nested/state.json.tmp").exists());
+
+    store.save(&PersistedState::default()).unwrap();
+    assert_eq!(store.load().unwrap().count, 0);
+
+    // state from before names were counted
+    std::fs::write(store.path(), r#"{ "count": 7 }"#).unwrap();
+    assert_eq!(store.load().unwrap().count, 7);
+    store.check_writable().unwrap();
+}
+
+#[test]
+fn moves_unparseable_state_aside() {
+    let dir = temp_dir("corrupt");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("state.json");
+    std::fs::write(&path, r#"{ "count": 4"#).unwrap();
+    let err = StateStore::default().with_path(&path).load().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    let mut app = start(&path);
+    assert!(app.should_exit().is_none());
+    assert_eq!(count(&mut app), 0);
+    assert!(!path.exists());
+    let backups: Vec<String> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(backups.len(), 1, "{backups:?}");
+    let secs = backups[0]
+        .strip_prefix("state.json.")
+        .and_then(|name| name.strip_suffix(".corrupt"))
+        .unwrap();
+    assert!(secs.parse::<u64>().is_ok(), "{secs}");
+    assert_eq!(
+        std::fs::read_to_string(dir.join(&backups[0])).unwrap(),
+        r#"{ "count": 4"#
+    );
+}
+
+#[test]
+fn exits_if_state_is_unreadable() {
+    // a directory can't be read as a file
+    let path = temp_dir("unreadable");
+    std::fs::create_dir_all(path.join("keep")).unwrap();
+    let err = StateStore::default().with_path(&path).load().unwrap_err();
+    assert_ne!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    let mut app = start(&path);
+    assert_eq!(app.should_exit(), Some(AppExit::from_code(IO_EXIT_CODE)));
+    app.update();
+    // left alone, rather than moved aside or overwritten
+    assert!(path.join("keep").is_dir());
+}